@@ -0,0 +1,60 @@
+// compares the case folding search_case_insensitive with the to_lowercase version it replaced
+//
+// #[bench] needs nightly Rust, so this is a plain program (harness = false in Cargo.toml) that
+// times each search with Instant, run it with: cargo bench --bench case_insensitive
+extern crate minigrep;
+
+use std::time::{Duration, Instant};
+
+use minigrep::search_case_insensitive;
+
+const ITERATIONS: u32 = 20;
+
+// the original implementation, allocating a lowercased String for every line
+fn search_to_lowercase<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    let mut results = Vec::new();
+
+    for line in contents.lines() {
+        if line.to_lowercase().contains(&query) {
+            results.push(line);
+        }
+    }
+
+    results
+}
+
+// runs a search ITERATIONS times, returning the average time and how many lines it found
+fn time<F: Fn() -> usize>(search: F) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut found = 0;
+
+    for _ in 0..ITERATIONS {
+        found = search();
+    }
+
+    (start.elapsed() / ITERATIONS, found)
+}
+
+fn main() {
+    let lines = [
+        "Rust:",
+        "safe, fast, productive.",
+        "Pick three.",
+        "Duct tape",
+        "2018-05-01 12:00:01 ERROR 500 request to /api/users failed after 3 retries",
+        "Straße, STRASSE und straße sind dasselbe Wort",
+    ];
+    let contents: Vec<&str> = lines.iter().cycle().take(300_000).cloned().collect();
+    let contents = contents.join("\n");
+
+    println!("{} lines, {} bytes, average of {} runs", 300_000, contents.len(), ITERATIONS);
+
+    for query in &["duct", "error 500", "STRASSE", "not in here"] {
+        let (folded, folded_found) = time(|| search_case_insensitive(query, &contents).len());
+        let (lowercase, lowercase_found) = time(|| search_to_lowercase(query, &contents).len());
+
+        println!("{:>12}  case folding {:>10.2?} ({} lines)  to_lowercase {:>10.2?} ({} lines)",
+                 query, folded, folded_found, lowercase, lowercase_found);
+    }
+}
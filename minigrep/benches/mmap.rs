@@ -0,0 +1,139 @@
+// compares three ways of searching a file: reading all of it into a String with read_to_string,
+// reading it a line at a time through a BufReader (Searcher::search_sink), and searching a memory
+// map of it (Searcher::search_slice), for how long each takes and the most heap each has in use
+//
+// a plain program like benches/case_insensitive.rs, run it with: cargo bench --bench mmap
+extern crate memmap2;
+extern crate minigrep;
+extern crate tempfile;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use memmap2::Mmap;
+use minigrep::{Record, Searcher, SearcherBuilder, Sink};
+
+// each size is searched enough times to get through about this many bytes
+const BYTES_SEARCHED: usize = 256 * 1024 * 1024;
+
+// the system allocator, keeping count of how many bytes are allocated and the most there's been
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// counts the selected lines, without asking for every match in them
+struct Count(usize);
+
+impl Sink for Count {
+    fn matched(&mut self, _record: &Record) -> io::Result<bool> {
+        self.0 += 1;
+        Ok(true)
+    }
+
+    fn wants_all_spans(&self) -> bool {
+        false
+    }
+}
+
+// one way of searching the file at a path, returning how many lines were selected
+type Strategy = fn(&Searcher, &Path) -> io::Result<usize>;
+
+fn read_to_string(searcher: &Searcher, path: &Path) -> io::Result<usize> {
+    let contents = fs::read_to_string(path)?;
+    searcher.search_slice(contents.as_bytes(), &mut Count(0))
+}
+
+fn buffered(searcher: &Searcher, path: &Path) -> io::Result<usize> {
+    searcher.search_sink(File::open(path)?, &mut Count(0))
+}
+
+fn mmap(searcher: &Searcher, path: &Path) -> io::Result<usize> {
+    let map = unsafe { Mmap::map(&File::open(path)?)? };
+    searcher.search_slice(&map, &mut Count(0))
+}
+
+// runs a search iterations times, returning the average time, how many lines it found and the
+// most heap it used on top of what was already allocated
+fn time<F: Fn() -> io::Result<usize>>(iterations: usize, search: F) -> (Duration, usize, usize) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let start = Instant::now();
+    let mut found = 0;
+
+    for _ in 0..iterations {
+        found = search().unwrap();
+    }
+
+    (start.elapsed() / iterations as u32, found, PEAK.load(Ordering::SeqCst) - before)
+}
+
+// a size in bytes as KiB or MiB
+fn size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{} MiB", bytes / 1024 / 1024)
+    } else {
+        format!("{} KiB", bytes / 1024)
+    }
+}
+
+fn main() {
+    let lines = [
+        "Rust:",
+        "safe, fast, productive.",
+        "Pick three.",
+        "Duct tape",
+        "2018-05-01 12:00:01 ERROR 500 request to /api/users failed after 3 retries",
+        "Straße, STRASSE und straße sind dasselbe Wort",
+    ];
+    let searcher = SearcherBuilder::new().pattern("ERROR 500").build().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    for &file_size in &[16 * 1024, 64 * 1024, 1024 * 1024, 64 * 1024 * 1024] {
+        let path = dir.path().join("haystack.txt");
+        let mut file = BufWriter::new(File::create(&path).unwrap());
+        let mut written = 0;
+        for line in lines.iter().cycle() {
+            if written + line.len() + 1 > file_size {
+                break;
+            }
+            writeln!(file, "{}", line).unwrap();
+            written += line.len() + 1;
+        }
+        file.flush().unwrap();
+        drop(file);
+
+        let iterations = (BYTES_SEARCHED / file_size).max(1);
+        println!("{} file, average of {} runs", size(file_size), iterations);
+
+        let strategies: [(&str, Strategy); 3] = [
+            ("read_to_string", read_to_string),
+            ("buffered", buffered),
+            ("mmap", mmap),
+        ];
+        for &(name, search) in &strategies {
+            let (elapsed, found, peak) = time(iterations, || search(&searcher, &path));
+            println!("{:>16} {:>10.2?} ({} lines)  peak heap {}", name, elapsed, found, size(peak));
+        }
+    }
+}
@@ -0,0 +1,701 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ignore::types::TypesBuilder;
+
+use encoding::Encoding;
+use matcher::Boundary;
+use STDIN;
+
+pub const USAGE: &str = "\
+usage: minigrep [OPTIONS] query [filename...]
+       minigrep [OPTIONS] -e PATTERN... [filename...]
+       minigrep [OPTIONS] -f PATTERNFILE [filename...]
+       minigrep [OPTIONS] index build [DIR...]
+       minigrep [OPTIONS] --interactive [query] [filename...]
+
+Searches each file (or stdin when none, or - is given) for lines matching query, or any of the
+patterns given with -e and -f. Directories are searched recursively.
+
+options:
+  -s, --case-sensitive      match case exactly (the default)
+  -i, --ignore-case         ignore case, also set by the CASE_INSENSITIVE env var
+  -S, --smart-case          ignore case unless the query has an uppercase letter in it
+  -E, --regex               treat query as a regular expression
+  -e, --regexp PATTERN      search for PATTERN, can be repeated to search for any of them
+  -f, --file PATTERNFILE    search for every pattern in PATTERNFILE, one per line
+      --fuzzy K             match text up to K edits (chars inserted, deleted or changed) away
+                            from query, printing how many edits each line's closest match is,
+                            as ~K: before the line
+  -w, --word-regexp         only match whole words, so duct doesn't match productive
+  -x, --line-regexp         only match whole lines
+  -U, --multiline           search each file as a whole, so a match can span lines (a regex
+                            needs \n to match a newline, or (?s) for . to); every line a match
+                            covers is printed, and they count as one for -c and -m
+  -v, --invert-match        select lines that don't match
+  -c, --count               print a count of selected lines per file
+  -l, --files-with-matches  print only the names of files with selected lines
+  -L, --files-without-match print only the names of files without selected lines
+  -m, --max-count N         stop reading a file after N selected lines
+  -q, --quiet               print nothing, exit 0 if anything was selected
+  -r, --replace TEXT        print selected lines with each match replaced by TEXT, in regex
+                            mode $1 or ${name} is what a capture group matched
+      --in-place            with --replace, rewrite the files searched instead of printing
+      --dry-run             with --in-place, print a diff of the changes without writing them
+      --interactive         browse the hits in a terminal UI, grouped by file with a preview of
+                            the lines around the selected one; the query (starting from query,
+                            if given) is searched for again as it's typed, up and down (or ctrl-p
+                            and ctrl-n) and page up and down move, enter opens the hit in $EDITOR
+                            and esc quits; without filenames it searches the current directory
+      --json                print a JSON object per line: a begin and end record for each
+                            file, a match record for each selected line and a summary at the end
+  -n, --line-number         prefix each line with its line number
+  -b, --byte-offset         prefix each line with its byte offset
+      --show-pattern        prefix each line with the pattern that matched it
+      --color WHEN          highlight matches: auto (the default), always or never,
+                            auto colors only when printing to a terminal and NO_COLOR isn't set
+  -A, --after-context N     print N lines of context after each match
+  -B, --before-context N    print N lines of context before each match
+  -C, --context N           print N lines of context before and after each match
+      --follow              keep searching the file after its end, like tail -f, printing lines
+                            as they're appended, until killed; follows the log across rotation
+  -z, --search-zip          decompress stdin too when it's in gzip, bzip2, xz or zstd format;
+                            files in those formats are always decompressed, whatever their name
+  -a, --text                search binary files as text, printing their lines; without it a
+                            binary file (one with a NUL byte near the start) named on the
+                            command line only prints \"Binary file X matches\", and ones found in
+                            directories are skipped
+      --encoding ENC        decode input from ENC before searching: auto (the default, UTF-16
+                            when there's a byte order mark, otherwise UTF-8), utf-8, utf-16le,
+                            utf-16be or latin1
+      --no-mmap             always read files, rather than memory mapping big ones
+      --include GLOB        only search files matching GLOB (can be repeated)
+      --exclude GLOB        skip files and directories matching GLOB (can be repeated)
+  -t, --type TYPE           only search files of TYPE, e.g. rust or js (can be repeated)
+  -T, --type-not TYPE       skip files of TYPE (can be repeated)
+      --type-list           print the file types -t and -T know about
+      --hidden              search hidden files and directories too
+      --no-ignore           don't skip files matched by .gitignore, .ignore or .minigrepignore
+      --index               use the index built by `minigrep index build DIR` for each DIR
+                            searched, to skip files that can't match; files changed since it was
+                            built are searched as usual, so the results are the same without it
+  -j, --threads N           search up to N files at once (output order stays the same)
+      --sort-files          search files in order of their path
+      --no-hidden           skip hidden files, undoing --hidden in the config file
+      --ignore              skip ignored files, undoing --no-ignore in the config file
+      --mmap                memory map big files, undoing --no-mmap in the config file
+      --no-config           don't read the config file
+  -h, --help                print this help
+  -V, --version             print the version
+  --                        stop parsing options, so a query can start with -
+
+default flags can be put in a config file, one per line (a value can go on the same line, as
+--context=2 or --context 2, or the next), with # starting a comment at the start of a line or
+after a space following a flag, so a value with \" #\" in it goes on a line of its own; -e and -f
+can't go in it. It's read from $MINIGREP_CONFIG, or else $XDG_CONFIG_HOME/minigrep/config or
+~/.config/minigrep/config. Later settings override earlier ones: built in defaults, then the
+CASE_INSENSITIVE env var, the config file and last of all the command line.
+
+matching is case sensitive unless the CASE_INSENSITIVE env var is set (to anything). It replaces
+CASE_SENSITIVE, which older versions read to turn case sensitivity on and which is now ignored, as
+that's the default.
+
+exit status is 0 if a line is selected, 1 if no lines were selected and 2 if an error occurred.";
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// every flag minigrep knows, as (short name, long name, takes a value)
+const FLAGS: &[(Option<char>, &str, bool)] = &[
+    (Some('s'), "case-sensitive", false),
+    (Some('i'), "ignore-case", false),
+    (Some('S'), "smart-case", false),
+    (Some('E'), "regex", false),
+    (Some('e'), "regexp", true),
+    (Some('f'), "file", true),
+    (None, "fuzzy", true),
+    (Some('w'), "word-regexp", false),
+    (Some('x'), "line-regexp", false),
+    (Some('U'), "multiline", false),
+    (Some('v'), "invert-match", false),
+    (Some('c'), "count", false),
+    (Some('l'), "files-with-matches", false),
+    (Some('L'), "files-without-match", false),
+    (Some('m'), "max-count", true),
+    (Some('q'), "quiet", false),
+    (None, "interactive", false),
+    (None, "json", false),
+    (Some('r'), "replace", true),
+    (None, "in-place", false),
+    (None, "dry-run", false),
+    (Some('n'), "line-number", false),
+    (Some('b'), "byte-offset", false),
+    (None, "show-pattern", false),
+    (None, "color", true),
+    (Some('A'), "after-context", true),
+    (Some('B'), "before-context", true),
+    (Some('C'), "context", true),
+    (None, "follow", false),
+    (Some('z'), "search-zip", false),
+    (Some('a'), "text", false),
+    (None, "encoding", true),
+    (None, "no-mmap", false),
+    (None, "include", true),
+    (None, "exclude", true),
+    (Some('t'), "type", true),
+    (Some('T'), "type-not", true),
+    (None, "type-list", false),
+    (None, "hidden", false),
+    (None, "no-ignore", false),
+    (None, "no-hidden", false),
+    (None, "ignore", false),
+    (None, "mmap", false),
+    (None, "no-config", false),
+    (None, "index", false),
+    (Some('j'), "threads", true),
+    (None, "sort-files", false),
+    (Some('h'), "help", false),
+    (Some('V'), "version", false),
+];
+
+// what run prints for each file searched
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputMode {
+    // every selected line, the default
+    Lines,
+    Count,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    // nothing at all, only the exit status matters
+    Quiet,
+    // JSON Lines, for editors and scripts to read
+    Json,
+}
+
+// when to highlight output with ANSI colors
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    // the first pattern, for when there's only the one
+    pub query: String,
+    // every pattern to search for, from the query or -e and -f
+    pub patterns: Vec<String>,
+    pub filenames: Vec<String>,
+    pub case_sensitive: bool,
+    // when set, case_sensitive is ignored and worked out from the query instead
+    pub smart_case: bool,
+    pub regex: bool,
+    // approximate matching, allowing up to this many edits
+    pub fuzzy: Option<usize>,
+    // whether a match has to be a whole word or line
+    pub boundary: Boundary,
+    // match against whole files rather than a line at a time
+    pub multiline: bool,
+    // select the lines that don't match instead
+    pub invert: bool,
+    pub mode: OutputMode,
+    // stop reading a file after this many selected lines
+    pub max_count: Option<usize>,
+    // what to replace matches with, and whether to do it in the files rather than the output
+    // (or only show what would change)
+    pub replace: Option<String>,
+    pub in_place: bool,
+    pub dry_run: bool,
+    // keep reading the one file named as it grows, like tail -f
+    pub follow: bool,
+    // browse the hits in a terminal UI, refining the query as it's typed
+    pub interactive: bool,
+    // check stdin for compression too, files are always checked
+    pub search_zip: bool,
+    // search binary files as if they were text
+    pub text: bool,
+    pub encoding: Encoding,
+    // search big files through a memory map, rather than reading them
+    pub mmap: bool,
+    // globs used to pick files when a filename is a directory
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // file types to search or skip, and whether to search hidden and ignored files anyway
+    pub types: Vec<String>,
+    pub types_not: Vec<String>,
+    pub hidden: bool,
+    pub no_ignore: bool,
+    // narrow down the files in directories with their trigram index, or build the index instead
+    // of searching, for `minigrep index build DIR`
+    pub index: bool,
+    pub build_index: bool,
+    // how many files to search at once, and whether to sort them by path first
+    pub threads: usize,
+    pub sort_files: bool,
+    // output options, like grep's -n, -b, -A and -B
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub show_pattern: bool,
+    pub color: ColorChoice,
+    pub before_context: usize,
+    pub after_context: usize
+}
+
+// everything that can stop Config::new giving back a Config, so callers (and tests) can match on
+// what happened rather than comparing strings; Help, Version and TypeList aren't really errors,
+// but main needs to print something and exit instead of searching
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    Help,
+    Version,
+    TypeList,
+    MissingQuery,
+    UnknownFlag(String),
+    MissingValue(String),
+    // a flag that doesn't take a value was given one, e.g. --regex=yes
+    UnexpectedValue(String),
+    // the flag and the value it couldn't use
+    InvalidValue(String, String),
+    // a flag that only works along with another one, e.g. --in-place without --replace
+    Requires(String, String),
+    // two flags that can't be used together
+    Conflicts(String, String),
+    // something other than a flag in the config file, which can't give a query or filenames
+    NotAFlag(String),
+    // -e or -f in the config file, which would take the query's place on every command line
+    PatternsInConfigFile,
+    // a config file that couldn't be read, and why
+    ConfigFile(String, String),
+    // a -f file that couldn't be read, and why
+    PatternFile(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::Version => write!(f, "minigrep {}", VERSION),
+            ConfigError::TypeList => {
+                let mut types = TypesBuilder::new();
+                types.add_defaults();
+                let lines: Vec<String> = types.definitions().iter()
+                    .map(|def| format!("{}: {}", def.name(), def.globs().join(", ")))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            },
+            ConfigError::MissingQuery => write!(f, "Didn't get a query string"),
+            ConfigError::UnknownFlag(ref flag) => write!(f, "Unknown flag {}", flag),
+            ConfigError::MissingValue(ref flag) => write!(f, "Missing value for {}", flag),
+            ConfigError::UnexpectedValue(ref flag) => write!(f, "{} doesn't take a value", flag),
+            ConfigError::InvalidValue(ref flag, ref value) => {
+                write!(f, "Invalid value for {}: {}", flag, value)
+            },
+            ConfigError::Requires(ref flag, ref needed) => write!(f, "{} only works with {}", flag, needed),
+            ConfigError::Conflicts(ref flag, ref other) => write!(f, "{} can't be used with {}", flag, other),
+            ConfigError::NotAFlag(ref arg) => write!(f, "{} in the config file isn't a flag", arg),
+            ConfigError::PatternsInConfigFile => write!(f, "-e and -f can't go in the config file"),
+            ConfigError::ConfigFile(ref filename, ref error) => {
+                write!(f, "Couldn't read config file {}: {}", filename, error)
+            },
+            ConfigError::PatternFile(ref filename, ref error) => {
+                write!(f, "Couldn't read patterns from {}: {}", filename, error)
+            },
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    // returns a Result<T, E>, OK gets unwrapped and Err is handled
+    // i.e. Result<OK(_), Err(e)>
+    // takes a slice of Strings (without the program name) returns a Result with Config
+    //
+    // flags can go anywhere, short flags can be combined (-in) and take values joined or as the
+    // next arg (-A2 or -A 2), long flags take values as --context=2 or --context 2; the first
+    // positional arg is the query and the rest are filenames
+    pub fn new(args: &[String]) -> Result<Config, ConfigError> {
+        Config::with_defaults(&[], args)
+    }
+
+    // the config main uses, with defaults from the config file (see config_file_args) unless
+    // --no-config is given
+    pub fn from_env(args: &[String]) -> Result<Config, ConfigError> {
+        // take_while stops at --, after which --no-config would be a query or filename
+        let no_config = args.iter().take_while(|arg| *arg != "--").any(|arg| arg == "--no-config");
+        let defaults = if no_config { Vec::new() } else { config_file_args()? };
+
+        Config::with_defaults(&defaults, args)
+    }
+
+    // like new, but with flags (and their values) to apply before args
+    //
+    // settings are layered, each one overriding those before it:
+    //   1. minigrep's built in defaults
+    //   2. the CASE_INSENSITIVE env var
+    //   3. defaults (from the config file)
+    //   4. args (from the command line)
+    pub fn with_defaults(defaults: &[String], args: &[String]) -> Result<Config, ConfigError> {
+        let mut config = Config {
+            query: String::new(),
+            patterns: Vec::new(),
+            filenames: Vec::new(),
+            // case sensitive by default, the CASE_INSENSITIVE env var being set turns it off
+            case_sensitive: env::var("CASE_INSENSITIVE").is_err(),
+            smart_case: false,
+            regex: false,
+            fuzzy: None,
+            boundary: Boundary::Anywhere,
+            multiline: false,
+            invert: false,
+            mode: OutputMode::Lines,
+            max_count: None,
+            replace: None,
+            in_place: false,
+            dry_run: false,
+            follow: false,
+            interactive: false,
+            search_zip: false,
+            text: false,
+            encoding: Encoding::Auto,
+            mmap: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            types: Vec::new(),
+            types_not: Vec::new(),
+            hidden: false,
+            no_ignore: false,
+            index: false,
+            build_index: false,
+            threads: 1,
+            sort_files: false,
+            line_number: false,
+            byte_offset: false,
+            show_pattern: false,
+            color: ColorChoice::Auto,
+            before_context: 0,
+            after_context: 0,
+        };
+
+        let mut positional = Vec::new();
+        // -f with an empty file gives no patterns, but still means there's no query arg
+        let mut patterns_given = false;
+
+        // the defaults go first, so the same flag on the command line replaces them
+        config.parse(defaults, &mut positional, &mut patterns_given)?;
+        if let Some(arg) = positional.first() {
+            return Err(ConfigError::NotAFlag(arg.clone()));
+        }
+        if patterns_given {
+            return Err(ConfigError::PatternsInConfigFile);
+        }
+        config.parse(args, &mut positional, &mut patterns_given)?;
+
+        // `index build` is a command of its own, with any args after it being the directories to
+        // index (-e index build searches for "index" in a file named build)
+        if !patterns_given && positional.len() >= 2 && positional[0] == "index" && positional[1] == "build" {
+            config.build_index = true;
+            config.filenames = positional.split_off(2);
+            if config.filenames.is_empty() {
+                config.filenames.push(".".to_string());
+            }
+            return Ok(config);
+        }
+
+        // without -e or -f the first positional arg is the query, otherwise they're all filenames
+        let mut positional = positional.into_iter();
+        if !patterns_given {
+            // the interactive query can be typed in once it's started
+            let query = match positional.next() {
+                Some(query) => query,
+                None if config.interactive => String::new(),
+                None => return Err(ConfigError::MissingQuery),
+            };
+            config.patterns.push(query);
+        }
+        config.query = config.patterns.first().cloned().unwrap_or_default();
+        config.filenames = positional.collect();
+
+        if config.in_place && config.replace.is_none() {
+            return Err(ConfigError::Requires("--in-place".to_string(), "--replace".to_string()));
+        }
+        if config.dry_run && !config.in_place {
+            return Err(ConfigError::Requires("--dry-run".to_string(), "--in-place".to_string()));
+        }
+        if config.fuzzy.is_some() && config.regex {
+            return Err(ConfigError::Conflicts("--fuzzy".to_string(), "--regex".to_string()));
+        }
+        if config.fuzzy.is_some() && config.patterns.len() > 1 {
+            return Err(ConfigError::Conflicts("--fuzzy".to_string(), "more than one pattern".to_string()));
+        }
+        if config.multiline && config.fuzzy.is_some() {
+            return Err(ConfigError::Conflicts("--multiline".to_string(), "--fuzzy".to_string()));
+        }
+        if config.multiline && config.in_place {
+            return Err(ConfigError::Conflicts("--multiline".to_string(), "--in-place".to_string()));
+        }
+        // every match in a file is replaced, there's no leaving some lines as they were
+        if config.invert && config.in_place {
+            return Err(ConfigError::Conflicts("--invert-match".to_string(), "--in-place".to_string()));
+        }
+        if config.max_count.is_some() && config.in_place {
+            return Err(ConfigError::Conflicts("--max-count".to_string(), "--in-place".to_string()));
+        }
+
+        if config.filenames.is_empty() && config.interactive {
+            config.filenames.push(".".to_string());
+        }
+        if config.filenames.is_empty() {
+            config.filenames.push(STDIN.to_string());
+        }
+
+        // the interactive browser searches its sources again for every change to the query, so
+        // they have to be files it can read again, all of them
+        if config.interactive {
+            let conflict = if config.filenames.iter().any(|filename| filename == STDIN) {
+                Some("standard input")
+            } else if config.follow {
+                Some("--follow")
+            } else if config.in_place {
+                Some("--in-place")
+            } else if config.index {
+                Some("--index")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::Conflicts("--interactive".to_string(), conflict.to_string()));
+            }
+        }
+
+        // following never gets to the end of the file, so can't print anything that waits for it
+        if config.follow {
+            if config.filenames.len() > 1 || config.filenames[0] == STDIN {
+                return Err(ConfigError::Requires("--follow".to_string(), "a single file".to_string()));
+            }
+            let conflict = match config.mode {
+                OutputMode::Count => Some("--count"),
+                OutputMode::FilesWithoutMatch => Some("--files-without-match"),
+                _ if config.multiline => Some("--multiline"),
+                _ if config.in_place => Some("--in-place"),
+                _ => None,
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::Conflicts("--follow".to_string(), conflict.to_string()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    // applies each flag in args, collecting the other args in positional
+    fn parse(&mut self, args: &[String], positional: &mut Vec<String>, patterns_given: &mut bool)
+        -> Result<(), ConfigError> {
+        // a while let loop is used so flags can take the next arg as their value
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                // by_ref borrows the iterator, so we can drain what's left of it here
+                positional.extend(args.by_ref().cloned());
+            } else if let Some(name) = arg.strip_prefix("--") {
+                let (name, inline) = match name.find('=') {
+                    Some(index) => (&name[..index], Some(name[index + 1..].to_string())),
+                    None => (name, None),
+                };
+                let &(_, long, takes_value) = FLAGS.iter()
+                    .find(|flag| flag.1 == name)
+                    .ok_or_else(|| ConfigError::UnknownFlag(arg.to_string()))?;
+    
+                let value = match (takes_value, inline) {
+                    (true, Some(value)) => Some(value),
+                    (true, None) => Some(next_value(arg, args.next())?),
+                    (false, Some(_)) => return Err(ConfigError::UnexpectedValue(arg.to_string())),
+                    (false, None) => None,
+                };
+                *patterns_given |= long == "regexp" || long == "file";
+                self.set(long, arg, value)?;
+            } else if arg.starts_with('-') && arg != STDIN {
+                // each char is a flag, until one that takes a value eats the rest of the arg
+                let mut chars = arg[1..].chars();
+                while let Some(short) = chars.next() {
+                    let flag = format!("-{}", short);
+                    let &(_, long, takes_value) = FLAGS.iter()
+                        .find(|flag| flag.0 == Some(short))
+                        .ok_or_else(|| ConfigError::UnknownFlag(flag.clone()))?;
+    
+                    if takes_value {
+                        let rest: String = chars.by_ref().collect();
+                        let value = if rest.is_empty() { next_value(&flag, args.next())? } else { rest };
+                        *patterns_given |= long == "regexp" || long == "file";
+                        self.set(long, &flag, Some(value))?;
+                    } else {
+                        self.set(long, &flag, None)?;
+                    }
+                }
+            } else {
+                positional.push(arg.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    // applies a flag (by its long name) to the config, the last flag given wins, though globs can
+    // be repeated; `flag` is the name as the user typed it, for error messages
+    fn set(&mut self, long: &str, flag: &str, value: Option<String>) -> Result<(), ConfigError> {
+        let value = value.unwrap_or_default();
+
+        match long {
+            "case-sensitive" => {
+                self.case_sensitive = true;
+                self.smart_case = false;
+            },
+            "ignore-case" => {
+                self.case_sensitive = false;
+                self.smart_case = false;
+            },
+            "smart-case" => self.smart_case = true,
+            "regex" => self.regex = true,
+            "regexp" => self.patterns.push(value),
+            "file" => {
+                let contents = fs::read_to_string(&value)
+                    .map_err(|e| ConfigError::PatternFile(value.clone(), e.to_string()))?;
+                self.patterns.extend(contents.lines().map(|line| line.to_string()));
+            },
+            "fuzzy" => self.fuzzy = Some(parse_number(flag, &value)?),
+            "word-regexp" => self.boundary = Boundary::Word,
+            "line-regexp" => self.boundary = Boundary::Line,
+            "multiline" => self.multiline = true,
+            "invert-match" => self.invert = true,
+            "count" => self.mode = OutputMode::Count,
+            "files-with-matches" => self.mode = OutputMode::FilesWithMatches,
+            "files-without-match" => self.mode = OutputMode::FilesWithoutMatch,
+            "max-count" => self.max_count = Some(parse_number(flag, &value)?),
+            "quiet" => self.mode = OutputMode::Quiet,
+            "json" => self.mode = OutputMode::Json,
+            "replace" => self.replace = Some(value),
+            "in-place" => self.in_place = true,
+            "dry-run" => self.dry_run = true,
+            "line-number" => self.line_number = true,
+            "byte-offset" => self.byte_offset = true,
+            "show-pattern" => self.show_pattern = true,
+            "color" => {
+                self.color = match value.as_str() {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    _ => return Err(ConfigError::InvalidValue(flag.to_string(), value)),
+                }
+            },
+            "after-context" => self.after_context = parse_number(flag, &value)?,
+            "before-context" => self.before_context = parse_number(flag, &value)?,
+            "context" => {
+                self.after_context = parse_number(flag, &value)?;
+                self.before_context = self.after_context;
+            },
+            "follow" => self.follow = true,
+            "interactive" => self.interactive = true,
+            "search-zip" => self.search_zip = true,
+            "text" => self.text = true,
+            "encoding" => {
+                self.encoding = Encoding::from_name(&value)
+                    .ok_or_else(|| ConfigError::InvalidValue(flag.to_string(), value.clone()))?;
+            },
+            "no-mmap" => self.mmap = false,
+            "include" => self.include.push(value),
+            "exclude" => self.exclude.push(value),
+            "type" => self.types.push(value),
+            "type-not" => self.types_not.push(value),
+            "type-list" => return Err(ConfigError::TypeList),
+            "hidden" => self.hidden = true,
+            "no-ignore" => self.no_ignore = true,
+            "no-hidden" => self.hidden = false,
+            "ignore" => self.no_ignore = false,
+            "mmap" => self.mmap = true,
+            // only matters to from_env, before there's a Config
+            "no-config" => {},
+            "index" => self.index = true,
+            "threads" => {
+                self.threads = parse_number(flag, &value)?;
+                if self.threads == 0 {
+                    return Err(ConfigError::InvalidValue(flag.to_string(), value));
+                }
+            },
+            "sort-files" => self.sort_files = true,
+            "help" => return Err(ConfigError::Help),
+            "version" => return Err(ConfigError::Version),
+            _ => return Err(ConfigError::UnknownFlag(flag.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+// flags from the config file, one per line, skipping blank lines and # comments (on a line of
+// their own or after a flag); a flag's value can follow it on the same line after a space, as
+// --context 2, as well as --context=2 or on the next line; the file is $MINIGREP_CONFIG (set but empty means no config file), otherwise the
+// default one if it exists
+pub fn config_file_args() -> Result<Vec<String>, ConfigError> {
+    let path = match env::var_os("MINIGREP_CONFIG") {
+        Some(ref path) if path.is_empty() => return Ok(Vec::new()),
+        Some(path) => PathBuf::from(path),
+        None => match default_config_file() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(Vec::new()),
+        },
+    };
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| ConfigError::ConfigFile(path.display().to_string(), e.to_string()))?;
+
+    let mut args = Vec::new();
+    for line in contents.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // a value on a line of its own is kept whole, spaces, # and all
+        if !line.starts_with('-') {
+            args.push(line.to_string());
+            continue;
+        }
+
+        // after a flag, a # with a space before it starts a comment
+        let comment = line.char_indices()
+            .find(|&(index, c)| c == '#' && line[..index].ends_with(char::is_whitespace));
+        let line = match comment {
+            Some((index, _)) => line[..index].trim_end(),
+            None => line,
+        };
+        // --exclude=My Docs is already the flag and its value
+        match line.find(char::is_whitespace) {
+            Some(index) if !line[..index].contains('=') => {
+                args.push(line[..index].to_string());
+                args.push(line[index..].trim_start().to_string());
+            },
+            _ => args.push(line.to_string()),
+        }
+    }
+
+    Ok(args)
+}
+
+// $XDG_CONFIG_HOME/minigrep/config, with XDG_CONFIG_HOME defaulting to ~/.config
+fn default_config_file() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(dir.join("minigrep").join("config"))
+}
+
+fn next_value(flag: &str, value: Option<&String>) -> Result<String, ConfigError> {
+    value.cloned().ok_or_else(|| ConfigError::MissingValue(flag.to_string()))
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<usize, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue(flag.to_string(), value.to_string()))
+}
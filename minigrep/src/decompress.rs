@@ -0,0 +1,46 @@
+use std::io::{self, BufRead, BufReader};
+
+use bzip2::bufread::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+// the compression formats minigrep can read, each recognised by the magic bytes a file starts with
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+const MAGIC: &[(&[u8], Format)] = &[
+    (&[0x1f, 0x8b], Format::Gzip),
+    (b"BZh", Format::Bzip2),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Format::Xz),
+    (&[0x28, 0xb5, 0x2f, 0xfd], Format::Zstd),
+];
+
+// the format the bytes at the start of a file say it's in, if any
+pub fn detect(bytes: &[u8]) -> Option<Format> {
+    MAGIC.iter()
+        .find(|&&(magic, _)| bytes.starts_with(magic))
+        .map(|&(_, format)| format)
+}
+
+// peeks at the start of the reader and, if it's compressed, gives back a reader that decompresses
+// it on the fly as lines are read, otherwise gives back the reader as it was
+//
+// the Multi decoders keep going after the first compressed stream ends, as rotated logs are often
+// made by appending one gzip stream to another
+pub fn decompress<'a>(mut reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn BufRead + 'a>> {
+    let reader: Box<dyn BufRead + 'a> = match detect(reader.fill_buf()?) {
+        Some(Format::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Some(Format::Bzip2) => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+        Some(Format::Xz) => Box::new(BufReader::new(XzDecoder::new_multi_decoder(reader))),
+        Some(Format::Zstd) => Box::new(BufReader::new(ZstdDecoder::with_buffer(reader)?)),
+        None => reader,
+    };
+
+    Ok(reader)
+}
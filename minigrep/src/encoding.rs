@@ -0,0 +1,147 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+// the text encodings minigrep can search, everything is decoded to UTF-8 before it's searched
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Encoding {
+    // UTF-16 if the input starts with a UTF-16 byte order mark, otherwise UTF-8
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // ISO-8859-1, where each byte is the char with the same number
+    Latin1,
+}
+
+impl Encoding {
+    // the encoding an --encoding value names, if it's one minigrep knows
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Some(Encoding::Auto),
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+}
+
+// byte order marks, and the encoding each one says the rest of the input is in
+const BOMS: &[(&[u8], Encoding)] = &[
+    (&[0xef, 0xbb, 0xbf], Encoding::Utf8),
+    (&[0xff, 0xfe], Encoding::Utf16Le),
+    (&[0xfe, 0xff], Encoding::Utf16Be),
+];
+
+// the encoding input starting with bytes is in, and how long its byte order mark is; a BOM is
+// only believed with --encoding auto, or skipped when it agrees with the encoding given
+fn detect(encoding: Encoding, bytes: &[u8]) -> (Encoding, usize) {
+    let bom = BOMS.iter().find(|&&(bom, _)| bytes.starts_with(bom));
+
+    match (encoding, bom) {
+        (Encoding::Auto, Some(&(bom, found))) => (found, bom.len()),
+        (Encoding::Auto, None) => (Encoding::Utf8, 0),
+        (_, Some(&(bom, found))) if found == encoding => (encoding, bom.len()),
+        _ => (encoding, 0),
+    }
+}
+
+// whether input starting with bytes can be searched as it is, as UTF-8 with no BOM to skip (any
+// invalid UTF-8 is replaced with U+FFFD as lines are read)
+pub fn is_utf8(encoding: Encoding, bytes: &[u8]) -> bool {
+    detect(encoding, bytes) == (Encoding::Utf8, 0)
+}
+
+// peeks at the start of the reader for a byte order mark and gives back a reader that decodes
+// the rest to UTF-8 as it's read, or the reader as it was if it's UTF-8 already
+//
+// byte offsets (-b) are then offsets into the decoded text rather than the file
+pub fn decode<'a>(mut reader: Box<dyn BufRead + 'a>, encoding: Encoding) -> io::Result<Box<dyn BufRead + 'a>> {
+    let (encoding, bom) = detect(encoding, reader.fill_buf()?);
+    reader.consume(bom);
+
+    let reader: Box<dyn BufRead + 'a> = match encoding {
+        Encoding::Utf16Le | Encoding::Utf16Be | Encoding::Latin1 => Box::new(BufReader::new(Decoder::new(reader, encoding))),
+        Encoding::Auto | Encoding::Utf8 => reader,
+    };
+
+    Ok(reader)
+}
+
+// decodes UTF-16 or Latin-1 a buffer of input at a time
+struct Decoder<R> {
+    reader: R,
+    encoding: Encoding,
+    // decoded text waiting to be read, and how much of it has been
+    out: String,
+    read: usize,
+    // UTF-16 that's split across two buffers: the first byte of a code unit, or the first half
+    // of a surrogate pair
+    odd_byte: Option<u8>,
+    high_surrogate: Option<u16>,
+}
+
+impl<R: BufRead> Decoder<R> {
+    fn new(reader: R, encoding: Encoding) -> Decoder<R> {
+        Decoder { reader, encoding, out: String::new(), read: 0, odd_byte: None, high_surrogate: None }
+    }
+
+    fn decode_utf16(&mut self, bytes: &[u8]) {
+        let mut units = Vec::with_capacity(bytes.len() / 2 + 2);
+        units.extend(self.high_surrogate.take());
+
+        for &byte in bytes {
+            let first = match self.odd_byte.take() {
+                Some(first) => first,
+                None => {
+                    self.odd_byte = Some(byte);
+                    continue;
+                },
+            };
+            units.push(match self.encoding {
+                Encoding::Utf16Be => u16::from_be_bytes([first, byte]),
+                _ => u16::from_le_bytes([first, byte]),
+            });
+        }
+
+        // the other half of a pair at the very end can only be in the next buffer
+        if units.last().is_some_and(|unit| (0xd800..0xdc00).contains(unit)) {
+            self.high_surrogate = units.pop();
+        }
+        let chars = char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER));
+        self.out.extend(chars);
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.out.len() {
+            self.out.clear();
+            self.read = 0;
+
+            let bytes = self.reader.fill_buf()?.to_vec();
+            if bytes.is_empty() {
+                // the input ended part way through a char
+                if self.odd_byte.is_some() || self.high_surrogate.is_some() {
+                    self.odd_byte = None;
+                    self.high_surrogate = None;
+                    self.out.push(char::REPLACEMENT_CHARACTER);
+                    break;
+                }
+                return Ok(0);
+            }
+            self.reader.consume(bytes.len());
+
+            match self.encoding {
+                Encoding::Latin1 => self.out.extend(bytes.iter().map(|&byte| byte as char)),
+                _ => self.decode_utf16(&bytes),
+            }
+        }
+
+        let len = buf.len().min(self.out.len() - self.read);
+        buf[..len].copy_from_slice(&self.out.as_bytes()[self.read..self.read + len]);
+        self.read += len;
+
+        Ok(len)
+    }
+}
@@ -0,0 +1,197 @@
+use std::ops::Range;
+
+// full Unicode case folding for the chars where it differs from char::to_lowercase, mostly ones
+// that fold to more than one char (see CaseFolding.txt, status C and F)
+const SPECIAL_FOLDS: &[(char, &str)] = &[
+    ('ß', "ss"),
+    ('ẞ', "ss"),
+    // Turkish dotted capital I folds to i followed by a combining dot above
+    ('İ', "i\u{307}"),
+    ('ŉ', "\u{2bc}n"),
+    ('ſ', "s"),
+    ('ς', "σ"),
+    ('ϐ', "β"),
+    ('ϑ', "θ"),
+    ('ϕ', "φ"),
+    ('ϖ', "π"),
+    ('ϰ', "κ"),
+    ('ϱ', "ρ"),
+    ('ϵ', "ε"),
+    ('ẛ', "ṡ"),
+    ('ι', "ι"),
+    ('ﬀ', "ff"),
+    ('ﬁ', "fi"),
+    ('ﬂ', "fl"),
+    ('ﬃ', "ffi"),
+    ('ﬄ', "ffl"),
+    ('ﬅ', "st"),
+    ('ﬆ', "st"),
+];
+
+// the case folded form of a single char, which is up to three chars long; an iterator over a
+// fixed size array means folding never needs to allocate
+pub struct Fold {
+    chars: [char; 3],
+    len: usize,
+    index: usize,
+}
+
+impl Fold {
+    // a char left as it is, for when case matters
+    pub fn unchanged(c: char) -> Fold {
+        Fold { chars: [c, '\0', '\0'], len: 1, index: 0 }
+    }
+}
+
+impl Iterator for Fold {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.index == self.len {
+            return None;
+        }
+        self.index += 1;
+        Some(self.chars[self.index - 1])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len - self.index, Some(self.len - self.index))
+    }
+}
+
+// with an exact size_hint, .len() tells us how many chars are left
+impl ExactSizeIterator for Fold {}
+
+pub fn fold(c: char) -> Fold {
+    let mut fold = Fold { chars: ['\0'; 3], len: 0, index: 0 };
+
+    // ASCII is by far the most common case, so skip the table lookup for it
+    if c.is_ascii() {
+        fold.chars[0] = c.to_ascii_lowercase();
+        fold.len = 1;
+        return fold;
+    }
+
+    match SPECIAL_FOLDS.iter().find(|&&(special, _)| special == c) {
+        Some(&(_, folded)) => {
+            for c in folded.chars() {
+                fold.chars[fold.len] = c;
+                fold.len += 1;
+            }
+        },
+        None => {
+            for c in c.to_lowercase() {
+                fold.chars[fold.len] = c;
+                fold.len += 1;
+            }
+        },
+    }
+
+    fold
+}
+
+// a query folded once up front, then matched against lines folding them a char at a time as it
+// goes, so unlike to_lowercase there's no new String per line and the spans found are byte
+// ranges in the original line (ß matches SS, and matching stops at whole chars of the line)
+#[derive(Debug)]
+pub struct FoldedQuery {
+    chars: Vec<char>,
+}
+
+impl FoldedQuery {
+    pub fn new(query: &str) -> FoldedQuery {
+        FoldedQuery { chars: query.chars().flat_map(fold).collect() }
+    }
+
+    // the byte range of the first match in the line, if there is one
+    pub fn find(&self, line: &str) -> Option<Range<usize>> {
+        self.find_from(line, 0)
+    }
+
+    // like find, but only for matches starting at or after byte from
+    pub fn find_from(&self, line: &str, from: usize) -> Option<Range<usize>> {
+        let first = match self.chars.first() {
+            Some(&first) => first,
+            None => return Some(from..from),
+        };
+        let bytes = line.as_bytes();
+
+        // work on bytes rather than chars, as most text is ASCII and checking a byte against the
+        // query's first char is much quicker than decoding and folding every char
+        for start in from..bytes.len() {
+            let byte = bytes[start];
+            let candidate = if byte.is_ascii() {
+                byte.to_ascii_lowercase() as char == first
+            } else {
+                // only the first byte of a multi-byte char (not a continuation byte 10xxxxxx)
+                // starts a char, and a non-ASCII char might fold to the first char, e.g. ſ to s
+                byte >= 0xc0 && line[start..].chars().next().and_then(|c| fold(c).next()) == Some(first)
+            };
+
+            if candidate {
+                if let Some(end) = self.match_at(line, start) {
+                    return Some(start..end);
+                }
+            }
+        }
+
+        None
+    }
+
+    // if the query matches the line starting at byte start, the byte the match ends at
+    fn match_at(&self, line: &str, start: usize) -> Option<usize> {
+        let bytes = line.as_bytes();
+        let mut query = self.chars.iter();
+        let mut expected = query.next();
+        let mut index = start;
+
+        while index < bytes.len() {
+            if bytes[index].is_ascii() {
+                match expected {
+                    Some(&expected_char) if expected_char == bytes[index].to_ascii_lowercase() as char => {
+                        expected = query.next();
+                    },
+                    _ => return None,
+                }
+                index += 1;
+            } else {
+                let c = line[index..].chars().next()?;
+                for folded in fold(c) {
+                    match expected {
+                        Some(&expected_char) if expected_char == folded => expected = query.next(),
+                        // a mismatch, or the query ended part way through a char's folding
+                        _ => return None,
+                    }
+                }
+                index += c.len_utf8();
+            }
+
+            if expected.is_none() {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+}
+
+// smart case searches are case insensitive unless the query has an uppercase letter in it; in a
+// regex, chars after a backslash are skipped so escapes like \W or \S don't count, and so is the
+// whole class name of \p{Lu} or \PL
+pub fn has_uppercase(query: &str, regex: bool) -> bool {
+    let mut chars = query.chars();
+
+    while let Some(c) = chars.next() {
+        if regex && c == '\\' {
+            if let Some('p') | Some('P') = chars.next() {
+                if chars.next() == Some('{') {
+                    chars.by_ref().find(|c| *c == '}');
+                }
+            }
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+
+    false
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use same_file::Handle;
+
+use output::Printer;
+use {encoding, print_source, Config, Searcher};
+
+// how long --follow waits at the end of the file before looking for more
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// a file being written to, read like `tail -f`: at the end, rather than read returning 0 it
+// waits until more is appended, so a search over it never finishes (unless it stops itself, as
+// with -m)
+//
+// it also survives the log being rotated, whether the file is truncated (copytruncate) or renamed
+// away and a new one created at the same path: either way it starts reading the new contents
+// from the start, line numbers and byte offsets carrying on from where they'd got to
+pub struct Follow {
+    path: PathBuf,
+    file: File,
+    // which file is open, to tell when the path has been given a different one
+    handle: Handle,
+    // how far into the file we've read
+    position: u64,
+    // whether the last byte read ended a line, so a rotation doesn't join a half written line
+    // onto the first line of the new file
+    line_ended: bool,
+    interval: Duration,
+}
+
+impl Follow {
+    // opens the file at path, which is checked for more every interval once the end is reached
+    pub fn open(path: &Path, interval: Duration) -> io::Result<Follow> {
+        let file = File::open(path)?;
+
+        Ok(Follow {
+            path: path.to_path_buf(),
+            handle: Handle::from_file(file.try_clone()?)?,
+            file,
+            position: 0,
+            line_ended: true,
+            interval,
+        })
+    }
+
+    // checks, at the end of the file, whether it's been rotated, opening or rewinding to the new
+    // contents and returning true if it has
+    fn rotated(&mut self) -> io::Result<bool> {
+        // renamed away with a new file in its place; until the new one is created there's no file
+        // at the path at all, and the old one might still be written to, so keep reading that
+        match Handle::from_path(&self.path) {
+            Ok(ref handle) if *handle != self.handle => {
+                let file = File::open(&self.path)?;
+                self.handle = Handle::from_file(file.try_clone()?)?;
+                self.file = file;
+                self.position = 0;
+                return Ok(true);
+            },
+            _ => {},
+        }
+
+        // truncated, so it's shorter than what we've read
+        if self.file.metadata()?.len() < self.position {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 {
+                self.position += read as u64;
+                self.line_ended = buf[read - 1] == b'\n';
+                return Ok(read);
+            }
+
+            if !self.rotated()? {
+                thread::sleep(self.interval);
+            } else if !self.line_ended {
+                buf[0] = b'\n';
+                self.line_ended = true;
+                return Ok(1);
+            }
+        }
+    }
+}
+
+// --follow: searches the file named on the command line, then keeps printing selected lines as
+// they're appended, until minigrep is killed (or -m or -q say to stop)
+pub fn run(config: &Config, searcher: &Searcher) -> Result<bool, Box<dyn Error>> {
+    let path = Path::new(&config.filenames[0]);
+    if path.is_dir() {
+        return Err(format!("{} is a directory, --follow needs a file", path.display()).into());
+    }
+    let follow = BufReader::new(Follow::open(path, POLL_INTERVAL)?);
+    let reader = encoding::decode(Box::new(follow), config.encoding)?;
+
+    let stdout = io::stdout();
+    let mut printer = Printer::new(config, false, stdout.lock());
+    let count = print_source(&mut printer, &path.display().to_string(), false, |printer| {
+        searcher.search_sink(reader, printer)
+    })?;
+    printer.summary()?;
+
+    Ok(count > 0)
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use fold::fold;
+use matcher::is_word_char;
+
+// the longest query --fuzzy can search for, as each query char is one bit of a u64
+pub const MAX_FUZZY_LEN: usize = 64;
+
+// approximate matching: finds the part of a line that can be turned into the query with the
+// fewest single char insertions, deletions or substitutions (the edit distance), as long as
+// that's no more than max_distance
+//
+// rather than working out the distance for every substring of the line, this uses Myers'
+// bit-parallel algorithm ("A fast bit-vector algorithm for approximate string matching based on
+// dynamic programming", 1999): the usual table of distances, with a row per query char and a
+// column per line char, only ever changes by -1, 0 or +1 between neighbouring cells, so a whole
+// column can be stored as two u64s of +1 and -1 bits and worked out from the last in a handful of
+// bit operations, making a search O(line length) for queries up to 64 chars
+#[derive(Debug)]
+pub struct FuzzyQuery {
+    // for each char of the query, a bit set at each position it appears at, for the query
+    // forwards (to find where a match ends) and backwards (to find where it starts)
+    forwards: Peq,
+    backwards: Peq,
+    len: usize,
+    max_distance: usize,
+    case_sensitive: bool,
+}
+
+// a match, and how many edits away from the query it is
+#[derive(Debug, PartialEq)]
+pub struct FuzzyMatch {
+    pub span: Range<usize>,
+    pub distance: usize,
+}
+
+// the bit masks for each char, with an array for ASCII so most lookups skip the HashMap
+#[derive(Debug)]
+struct Peq {
+    ascii: [u64; 128],
+    other: HashMap<char, u64>,
+}
+
+impl Peq {
+    fn new<I: Iterator<Item = char>>(chars: I) -> Peq {
+        let mut peq = Peq { ascii: [0; 128], other: HashMap::new() };
+
+        for (position, c) in chars.enumerate() {
+            let bit = 1 << position;
+            if c.is_ascii() {
+                peq.ascii[c as usize] |= bit;
+            } else {
+                *peq.other.entry(c).or_insert(0) |= bit;
+            }
+        }
+
+        peq
+    }
+
+    fn get(&self, c: char) -> u64 {
+        if c.is_ascii() {
+            self.ascii[c as usize]
+        } else {
+            self.other.get(&c).cloned().unwrap_or(0)
+        }
+    }
+}
+
+impl FuzzyQuery {
+    pub fn new(query: &str, max_distance: usize, case_sensitive: bool) -> Result<FuzzyQuery, String> {
+        let chars: Vec<char> = query.chars().map(|c| fold_char(c, case_sensitive)).collect();
+        if chars.len() > MAX_FUZZY_LEN {
+            return Err(format!("--fuzzy queries can be at most {} chars long", MAX_FUZZY_LEN));
+        }
+
+        Ok(FuzzyQuery {
+            forwards: Peq::new(chars.iter().cloned()),
+            backwards: Peq::new(chars.iter().rev().cloned()),
+            len: chars.len(),
+            max_distance,
+            case_sensitive,
+        })
+    }
+
+    // the best match in the line, if there's one within max_distance: the one with the lowest
+    // distance, then the first to end (though when the next few ends are just as close, like
+    // d, du, duc and duct all being 2 edits from dcut, the last of them), then of those ending
+    // there, the one closest in length to the query
+    pub fn find(&self, line: &str) -> Option<FuzzyMatch> {
+        self.find_from(line, 0)
+    }
+
+    // like find, but only looking at the line from byte from on
+    pub fn find_from(&self, line: &str, from: usize) -> Option<FuzzyMatch> {
+        // the empty string is len edits (deleting every char) from the query, so with a short
+        // enough query there's always a match
+        let mut best = if self.len <= self.max_distance { Some((from, self.len)) } else { None };
+        let mut in_run = false;
+
+        // searching, a match can start anywhere, so each column starts at the top from 0
+        let mut distances = Distances::new(&self.forwards, self.len, false);
+        for (index, c) in line[from..].char_indices() {
+            let end = from + index + c.len_utf8();
+            let distance = distances.step(fold_char(c, self.case_sensitive));
+
+            if distance <= self.max_distance && best.is_none_or(|(_, best)| distance < best) {
+                best = Some((end, distance));
+                in_run = true;
+            } else if in_run && best.is_some_and(|(_, best)| distance == best) {
+                best = Some((end, distance));
+            } else {
+                in_run = false;
+            }
+
+            // nothing beats an exact match
+            if distance == 0 {
+                break;
+            }
+        }
+        let (end, distance) = best?;
+
+        // going backwards from the end with the query reversed, the match having to end at the
+        // end, gives the distance for each place the match could start; a match more than
+        // max_distance chars longer than the query would need more than that many deletions
+        let mut start = (self.len, end);
+        let mut distances = Distances::new(&self.backwards, self.len, true);
+        let chars = line[from..end].char_indices().rev().take(self.len + self.max_distance);
+        for (length, (index, c)) in chars.enumerate().map(|(count, c)| (count + 1, c)) {
+            let off_by = length.abs_diff(self.len);
+            if distances.step(fold_char(c, self.case_sensitive)) == distance && off_by < start.0 {
+                start = (off_by, from + index);
+            }
+        }
+
+        Some(FuzzyMatch { span: start.1..end, distance })
+    }
+
+    // with -w, rather than a match being any part of the line, it's the whole word (as in
+    // matcher::is_word_char) closest to the query, the first of them on a tie
+    pub fn find_word_from(&self, line: &str, from: usize) -> Option<FuzzyMatch> {
+        let mut best: Option<FuzzyMatch> = None;
+        let mut rest = from;
+
+        // a word that from lands in the middle of isn't whole
+        if line[..from].chars().next_back().is_some_and(is_word_char) {
+            rest = line[from..].find(|c| !is_word_char(c)).map_or(line.len(), |end| from + end);
+        }
+
+        while let Some(start) = line[rest..].find(is_word_char).map(|start| rest + start) {
+            let end = line[start..].find(|c| !is_word_char(c)).map_or(line.len(), |end| start + end);
+            let distance = self.distance(&line[start..end]);
+            if distance <= self.max_distance && best.as_ref().is_none_or(|best| distance < best.distance) {
+                best = Some(FuzzyMatch { span: start..end, distance });
+            }
+            rest = end;
+        }
+
+        best
+    }
+
+    // with -x, the whole line, if all of it is close enough to the query
+    pub fn find_line(&self, line: &str) -> Option<FuzzyMatch> {
+        let distance = self.distance(line);
+        if distance <= self.max_distance {
+            Some(FuzzyMatch { span: 0..line.len(), distance })
+        } else {
+            None
+        }
+    }
+
+    // how many edits all of text is from the query
+    fn distance(&self, text: &str) -> usize {
+        let mut distances = Distances::new(&self.forwards, self.len, true);
+        text.chars().fold(self.len, |_, c| distances.step(fold_char(c, self.case_sensitive)))
+    }
+}
+
+// one column of the distance table at a time, as vertical deltas: bit i of plus (minus) is set
+// when row i + 1 is one more (less) than row i
+struct Distances<'a> {
+    peq: &'a Peq,
+    plus: u64,
+    minus: u64,
+    // the bit of the last row, whose distance is the one we're after
+    last: u64,
+    mask: u64,
+    distance: usize,
+    // whether the text has to be matched from its first char (for the distance between the query
+    // and all of the text so far) rather than anywhere
+    anchored: bool,
+}
+
+impl<'a> Distances<'a> {
+    fn new(peq: &'a Peq, len: usize, anchored: bool) -> Distances<'a> {
+        let mask = if len == MAX_FUZZY_LEN { !0 } else { (1 << len) - 1 };
+
+        Distances {
+            peq,
+            // the first column is 0, 1, 2 and so on, going up by one every row
+            plus: mask,
+            minus: 0,
+            last: if len == 0 { 0 } else { 1 << (len - 1) },
+            mask,
+            distance: len,
+            anchored,
+        }
+    }
+
+    // moves on to the column for the next text char, giving the new distance in the last row
+    fn step(&mut self, c: char) -> usize {
+        let eq = self.peq.get(c);
+        let vertical = eq | self.minus;
+        let horizontal = (((eq & self.plus).wrapping_add(self.plus)) ^ self.plus) | eq;
+
+        // the horizontal deltas, between this column and the last
+        let mut plus = self.minus | !(horizontal | self.plus);
+        let mut minus = self.plus & horizontal;
+
+        if plus & self.last != 0 {
+            self.distance += 1;
+        } else if minus & self.last != 0 {
+            self.distance -= 1;
+        }
+
+        // shifting moves each delta down a row; anchored, the top row goes up by one each column
+        plus <<= 1;
+        minus <<= 1;
+        if self.anchored {
+            plus |= 1;
+        }
+
+        self.plus = (minus | !(vertical | plus)) & self.mask;
+        self.minus = plus & vertical & self.mask;
+
+        self.distance
+    }
+}
+
+// ignoring case, chars are compared by their case folding, when it's a single char (so ß is
+// only the same as ß here, not ss)
+fn fold_char(c: char, case_sensitive: bool) -> char {
+    if case_sensitive {
+        return c;
+    }
+
+    let mut folded = fold(c);
+    match (folded.next(), folded.next()) {
+        (Some(folded), None) => folded,
+        _ => c,
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use regex_syntax::hir::literal::Extractor;
+use regex_syntax::ParserBuilder;
+
+use decompress;
+use encoding::{self, Encoding};
+use {walk, Config, OutputMode};
+
+// where `minigrep index build DIR` writes the index for DIR; it starts with a dot, so it's hidden
+// and isn't searched itself
+pub const INDEX_FILE: &str = ".minigrep-index";
+
+// the index format changing means a new version here, and rebuilding every index
+const MAGIC: &[u8] = b"minigrep index 1\n";
+
+// a trigram index of the files under a directory: for every three bytes in a row that are in any
+// of the files, which files they're in (like Google Code Search, see
+// https://swtch.com/~rsc/regexp/regexp4.html)
+//
+// a search for "needle" can only match files with all of nee, eed, edl and dle in them, so with
+// --index the other files aren't read at all; it's only ever used to leave files out, anything
+// the index doesn't know about, or that's changed since it was built, is searched as usual, so
+// --index finds the same lines as a search without it
+//
+// trigrams are ASCII lowercased, so the same index works with or without -i
+#[derive(Debug, Default)]
+struct Index {
+    files: Vec<Entry>,
+    // each trigram, with the indexes into files of the files it's in
+    postings: BTreeMap<u32, Vec<u32>>,
+}
+
+// a file as it was when it was indexed
+#[derive(Debug)]
+struct Entry {
+    // relative to the indexed directory
+    path: String,
+    len: u64,
+    // seconds and nanoseconds since the Unix epoch
+    modified: (u64, u32),
+    kind: Kind,
+    // whether it has a NUL byte near the start, so a search skips it without --text
+    binary: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Kind {
+    // compressed or UTF-16, so the trigrams of its bytes aren't those of what's searched
+    Unindexed,
+    // all ASCII, which a case insensitive search can rule out by its trigrams
+    Ascii,
+    // with non-ASCII text, which ignoring case could match in ways trigrams can't tell (ß matching
+    // ss, or the Kelvin sign matching k)
+    Text,
+}
+
+// what building an index did, for `minigrep index build` to report
+#[derive(Debug, PartialEq)]
+pub struct BuildStats {
+    pub files: usize,
+    // read because they're new or changed since the last build
+    pub read: usize,
+    // no longer there, or no longer walked
+    pub removed: usize,
+}
+
+impl Index {
+    fn read(path: &Path) -> io::Result<Index> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = vec![0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid(path));
+        }
+
+        let mut index = Index::default();
+        for _ in 0..read_u32(&mut reader)? {
+            // the length is only trusted as far as there are bytes to back it up, so a corrupt one
+            // can't ask for a huge allocation
+            let name_len = read_u32(&mut reader)? as usize;
+            let mut name = Vec::new();
+            (&mut reader).take(name_len as u64).read_to_end(&mut name)?;
+            if name.len() != name_len {
+                return Err(invalid(path));
+            }
+            let path = String::from_utf8(name).map_err(|_| invalid(path))?;
+            let len = read_u64(&mut reader)?;
+            let modified = (read_u64(&mut reader)?, read_u32(&mut reader)?);
+            // the kind in the low bits, and a bit for being binary
+            let flags = read_u32(&mut reader)?;
+            let kind = match flags & 3 {
+                0 => Kind::Unindexed,
+                1 => Kind::Ascii,
+                _ => Kind::Text,
+            };
+            index.files.push(Entry { path, len, modified, kind, binary: flags & 4 != 0 });
+        }
+
+        for _ in 0..read_u32(&mut reader)? {
+            let trigram = read_u32(&mut reader)?;
+            let count = read_u32(&mut reader)?;
+            let files = (0..count).map(|_| read_u32(&mut reader)).collect::<io::Result<Vec<u32>>>()?;
+            if files.iter().any(|&file| file as usize >= index.files.len()) {
+                return Err(invalid(path));
+            }
+            index.postings.insert(trigram, files);
+        }
+
+        Ok(index)
+    }
+
+    // writes to a temporary file first, so a search never sees half an index; like the temp
+    // files of --in-place, it's only ever created new, never written through something already
+    // there
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let temporary = path.with_file_name(format!("{}-{}", INDEX_FILE, process::id()));
+        let file = OpenOptions::new().write(true).create_new(true).open(&temporary)?;
+
+        let result = self.write_to(file).and_then(|_| fs::rename(&temporary, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        result
+    }
+
+    fn write_to(&self, file: File) -> io::Result<()> {
+        let mut out = BufWriter::new(file);
+
+        out.write_all(MAGIC)?;
+        out.write_all(&(self.files.len() as u32).to_le_bytes())?;
+        for entry in &self.files {
+            out.write_all(&(entry.path.len() as u32).to_le_bytes())?;
+            out.write_all(entry.path.as_bytes())?;
+            out.write_all(&entry.len.to_le_bytes())?;
+            out.write_all(&entry.modified.0.to_le_bytes())?;
+            out.write_all(&entry.modified.1.to_le_bytes())?;
+            let kind: u32 = match entry.kind {
+                Kind::Unindexed => 0,
+                Kind::Ascii => 1,
+                Kind::Text => 2,
+            };
+            let binary = if entry.binary { 4 } else { 0 };
+            out.write_all(&(kind | binary).to_le_bytes())?;
+        }
+
+        out.write_all(&(self.postings.len() as u32).to_le_bytes())?;
+        for (trigram, files) in &self.postings {
+            out.write_all(&trigram.to_le_bytes())?;
+            out.write_all(&(files.len() as u32).to_le_bytes())?;
+            for file in files {
+                out.write_all(&file.to_le_bytes())?;
+            }
+        }
+
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+}
+
+// indexes every file a search of root would look at (so -t, --hidden and so on count), writing
+// root/.minigrep-index; files that haven't changed since the last build (going by their size and
+// modification time) keep the trigrams they had, only new and changed ones are read; anything
+// that couldn't be read is added to errors and left out of the index, so a search still reads it
+pub fn build(root: &Path, config: &Config, errors: &mut Vec<ignore::Error>)
+    -> Result<BuildStats, Box<dyn Error>> {
+    if !root.is_dir() {
+        return Err(format!("{} isn't a directory, only directories can be indexed", root.display()).into());
+    }
+    let index_path = root.join(INDEX_FILE);
+    // an old index that can't be read is only rebuilt from scratch
+    let old = Index::read(&index_path).unwrap_or_default();
+
+    // each old file's trigrams, by turning the postings back around
+    let mut old_trigrams: Vec<Vec<u32>> = vec![Vec::new(); old.files.len()];
+    for (&trigram, files) in &old.postings {
+        for &file in files {
+            old_trigrams[file as usize].push(trigram);
+        }
+    }
+    let mut old_files: HashMap<&str, usize> = old.files.iter().enumerate()
+        .map(|(id, entry)| (entry.path.as_str(), id))
+        .collect();
+
+    let mut index = Index::default();
+    let mut read = 0;
+    for file in walk::files(root, config, errors)? {
+        let metadata = match fs::metadata(&file) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                errors.push(unreadable(&file, e));
+                continue;
+            },
+        };
+        let path = relative(root, &file);
+        let (len, modified) = (metadata.len(), modified(&metadata));
+        let id = index.files.len() as u32;
+
+        let unchanged = old_files.remove(path.as_str())
+            .filter(|&old_id| old.files[old_id].len == len && old.files[old_id].modified == modified);
+        let (kind, binary, trigrams) = match unchanged {
+            Some(old_id) => (old.files[old_id].kind, old.files[old_id].binary, old_trigrams[old_id].clone()),
+            None => {
+                let bytes = match fs::read(&file) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        errors.push(unreadable(&file, e));
+                        continue;
+                    },
+                };
+                read += 1;
+                let (kind, trigrams) = file_trigrams(&bytes);
+                (kind, walk::is_binary(&bytes), trigrams)
+            },
+        };
+
+        for trigram in trigrams {
+            index.postings.entry(trigram).or_default().push(id);
+        }
+        index.files.push(Entry { path, len, modified, kind, binary });
+    }
+
+    index.write(&index_path)?;
+    Ok(BuildStats { files: index.files.len(), read, removed: old_files.len() })
+}
+
+// the files under root a search with --index can skip
+pub struct Filter {
+    // files that can't match, with the size and modification time they had when they were indexed
+    // and whether they're binary
+    skip: HashMap<String, (u64, (u64, u32), bool)>,
+}
+
+impl Filter {
+    // reads root's index and works out which of its files can't match config's patterns
+    pub fn new(root: &Path, config: &Config) -> Result<Filter, Box<dyn Error>> {
+        let index = Index::read(&root.join(INDEX_FILE)).map_err(|e| {
+            format!("couldn't read the index for {} ({}), build one with: minigrep index build {}",
+                    root.display(), e, root.display())
+        })?;
+        let ignore_case = !config.case_sensitive || config.smart_case;
+
+        let mut skip = HashMap::new();
+        if let Some(matching) = index.matching(&required(config)) {
+            for (id, entry) in index.files.into_iter().enumerate() {
+                let can_match = matching.contains(&(id as u32)) || match entry.kind {
+                    Kind::Unindexed => true,
+                    Kind::Ascii => false,
+                    Kind::Text => ignore_case,
+                };
+                if !can_match {
+                    skip.insert(entry.path, (entry.len, entry.modified, entry.binary));
+                }
+            }
+        }
+
+        Ok(Filter { skip })
+    }
+
+    // whether the index rules out file (found walking root) matching, which it only does when
+    // the file's the same size and age as when it was indexed; Some(binary) when it does
+    pub fn rules_out(&self, root: &Path, file: &Path) -> Option<bool> {
+        let &(len, when, binary) = self.skip.get(&relative(root, file))?;
+        let metadata = fs::metadata(file).ok()?;
+
+        if (len, when) == (metadata.len(), modified(&metadata)) {
+            Some(binary)
+        } else {
+            None
+        }
+    }
+}
+
+impl Index {
+    // the files with every trigram of at least one of the lists in required, None for all of them
+    fn matching(&self, required: &Option<Vec<Vec<u32>>>) -> Option<HashSet<u32>> {
+        let required = required.as_ref()?;
+        let mut matching = HashSet::new();
+
+        for trigrams in required {
+            let mut files: Option<HashSet<u32>> = None;
+            for trigram in trigrams {
+                let with = self.postings.get(trigram).map(|files| files.iter().cloned().collect()).unwrap_or_default();
+                files = Some(match files {
+                    Some(files) => files.intersection(&with).cloned().collect(),
+                    None => with,
+                });
+            }
+            matching.extend(files.unwrap_or_default());
+        }
+
+        Some(matching)
+    }
+}
+
+// the trigrams a file has to have to match config's patterns: all of those in at least one of the
+// lists, each list being what one literal (one pattern, or one of the literals a regex has to
+// start with) needs; None when any file could match, like with -v, --fuzzy, or a pattern without
+// three ASCII chars in a row
+fn required(config: &Config) -> Option<Vec<Vec<u32>>> {
+    let decoded = !matches!(config.encoding, Encoding::Auto | Encoding::Utf8);
+    if config.invert || config.fuzzy.is_some() || config.mode == OutputMode::FilesWithoutMatch || decoded {
+        return None;
+    }
+
+    let mut required = Vec::new();
+    for pattern in &config.patterns {
+        if config.regex {
+            let hir = ParserBuilder::new().build().parse(pattern).ok()?;
+            for literal in Extractor::new().extract(&hir).literals()? {
+                required.push(trigrams(literal.as_bytes()));
+            }
+        } else {
+            required.push(trigrams(pattern.as_bytes()));
+        }
+    }
+
+    if required.iter().any(|trigrams| trigrams.is_empty()) {
+        return None;
+    }
+    Some(required)
+}
+
+// the trigrams in a literal, from its runs of ASCII only, as with ignoring case a non-ASCII char
+// could match something else entirely
+fn trigrams(literal: &[u8]) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = literal.split(|byte| !byte.is_ascii())
+        .flat_map(|run| run.windows(3).map(trigram))
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+// what kind of file bytes are and every trigram in them; compressed and UTF-16 files are searched
+// once they're decoded, so their bytes would give the wrong trigrams
+fn file_trigrams(bytes: &[u8]) -> (Kind, Vec<u32>) {
+    if decompress::detect(bytes).is_some() || !encoding::is_utf8(Encoding::Auto, bytes) {
+        return (Kind::Unindexed, Vec::new());
+    }
+
+    let trigrams: HashSet<u32> = bytes.windows(3).map(trigram).collect();
+    let kind = if bytes.is_ascii() { Kind::Ascii } else { Kind::Text };
+    (kind, trigrams.into_iter().collect())
+}
+
+fn trigram(bytes: &[u8]) -> u32 {
+    let byte = |index: usize| u32::from(bytes[index].to_ascii_lowercase());
+    byte(0) << 16 | byte(1) << 8 | byte(2)
+}
+
+// how a file under root is named in the index
+fn relative(root: &Path, file: &Path) -> String {
+    file.strip_prefix(root).unwrap_or(file).to_string_lossy().into_owned()
+}
+
+fn modified(metadata: &fs::Metadata) -> (u64, u32) {
+    metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |since| (since.as_secs(), since.subsec_nanos()))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// a file that couldn't be indexed, reported the same way as the parts of the walk that failed
+fn unreadable(file: &Path, e: io::Error) -> ignore::Error {
+    ignore::Error::WithPath { path: file.to_path_buf(), err: Box::new(ignore::Error::Io(e)) }
+}
+
+fn invalid(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't a minigrep index", path.display()))
+}
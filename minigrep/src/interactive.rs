@@ -0,0 +1,652 @@
+use std::env;
+use std::error::Error;
+use std::io::{self, BufRead, Cursor};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use fold;
+use matcher::Boundary;
+use output::{paint, MATCH_COLOR, NAME_COLOR, NUMBER_COLOR, RESET};
+use {open_source, read_line, sources, Config, Input, Record, SearcherBuilder, Sink, Source};
+
+// stop collecting hits after this many, so a one letter query over a big tree stays quick to type
+const MAX_HITS: usize = 10_000;
+
+// on top of the printer's colors, one for what's wrong with the query and reverse video for the
+// selected hit
+const ERROR_COLOR: &str = "\x1b[31m";
+const SELECTED: &str = "\x1b[7m";
+
+// a key pressed in the terminal
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Key {
+    Char(char),
+    // a letter typed with ctrl held down, like Ctrl('c')
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Esc,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+}
+
+// what the browser wants done after a key
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Redraw,
+    // open the file in $EDITOR at the line
+    Open(PathBuf, usize),
+    Quit,
+}
+
+// --interactive: the state of the terminal UI, kept apart from the terminal so it can be driven
+// by tests; keys go in through key and what's on screen comes out of render
+//
+// the query is searched for again as it's typed, over every source (walked once, up front), with
+// the hits grouped by file; below them is a preview of the lines around the selected hit
+pub struct Browser {
+    config: Config,
+    sources: Vec<Source>,
+    query: String,
+    // the query files is for, and whether it's every hit rather than the first MAX_HITS
+    searched: String,
+    complete: bool,
+    files: Vec<FileHits>,
+    // why the query couldn't be searched for, e.g. an unfinished regex
+    error: Option<String>,
+    // the selected hit, counting through every file's hits in order
+    selected: usize,
+    // the first row of hits on screen, and how many fit, for paging
+    scroll: usize,
+    page: usize,
+    preview: Option<Preview>,
+}
+
+// the lines around the selected hit, kept until the selection (or the room for them) changes
+struct Preview {
+    // the hit, and how many lines there were room for
+    key: (usize, usize),
+    // each line's number and text
+    lines: Vec<(usize, String)>,
+}
+
+// the hits in one source
+struct FileHits {
+    // which of the browser's sources
+    source: usize,
+    name: String,
+    records: Vec<Record>,
+}
+
+// one line of the list of hits
+enum Row {
+    File(usize),
+    // a file and which of its records, along with its number in every hit
+    Hit(usize, usize, usize),
+}
+
+impl Browser {
+    // walks the sources and searches for the query the config starts with
+    pub fn new(config: Config) -> Result<Browser, Box<dyn Error>> {
+        let mut browser = Browser {
+            // anything that couldn't be walked is left out, there's nowhere to say so on screen
+            sources: sources(&config, &mut Vec::new())?,
+            query: config.query.clone(),
+            config,
+            searched: String::new(),
+            complete: false,
+            files: Vec::new(),
+            error: None,
+            selected: 0,
+            scroll: 0,
+            page: 1,
+            preview: None,
+        };
+        browser.search();
+
+        Ok(browser)
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    // how many hits there are for the query
+    pub fn hits(&self) -> usize {
+        self.files.iter().map(|file| file.records.len()).sum()
+    }
+
+    pub fn key(&mut self, key: Key) -> Action {
+        match key {
+            Key::Esc | Key::Ctrl('c') | Key::Ctrl('d') => return Action::Quit,
+            Key::Enter => {
+                if let Some((file, record)) = self.hit(self.selected) {
+                    let line_number = self.files[file].records[record].line_number;
+                    match self.sources[self.files[file].source] {
+                        Source::File(ref path) | Source::Walked(ref path) => return Action::Open(path.clone(), line_number),
+                        _ => {},
+                    }
+                }
+            },
+            Key::Up | Key::Ctrl('p') => self.select(self.selected.saturating_sub(1)),
+            Key::Down | Key::Ctrl('n') => self.select(self.selected + 1),
+            Key::PageUp => self.select(self.selected.saturating_sub(self.page)),
+            Key::PageDown => self.select(self.selected + self.page),
+            Key::Home => self.select(0),
+            Key::End => self.select(self.hits().saturating_sub(1)),
+            Key::Backspace => {
+                self.query.pop();
+                self.search();
+            },
+            Key::Ctrl('u') => {
+                self.query.clear();
+                self.search();
+            },
+            Key::Char(c) => {
+                self.query.push(c);
+                self.search();
+            },
+            _ => {},
+        }
+
+        Action::Redraw
+    }
+
+    fn select(&mut self, hit: usize) {
+        self.selected = hit.min(self.hits().saturating_sub(1));
+    }
+
+    // the file and record of a hit
+    fn hit(&self, mut hit: usize) -> Option<(usize, usize)> {
+        for (index, file) in self.files.iter().enumerate() {
+            if hit < file.records.len() {
+                return Some((index, hit));
+            }
+            hit -= file.records.len();
+        }
+        None
+    }
+
+    // searches for the query again; when the query's only had text added to the end, and a
+    // longer match can only be where a shorter one is (so not for regexes, fuzzy matching, -v or
+    // -w and -x), only the files that had hits last time need searching
+    //
+    // ignoring case, a longer query can match where a shorter one doesn't (s doesn't match Maß,
+    // but ss does), so then every file is searched again
+    fn search(&mut self) {
+        let config = &self.config;
+        let case_sensitive = if config.smart_case {
+            fold::has_uppercase(&self.query, config.regex)
+        } else {
+            config.case_sensitive
+        };
+        let narrows = !config.regex && config.fuzzy.is_none() && !config.invert &&
+            config.boundary == Boundary::Anywhere && case_sensitive;
+        let candidates: Vec<usize> = if narrows && self.complete && !self.searched.is_empty() &&
+            self.query.starts_with(&self.searched) {
+            self.files.iter().map(|file| file.source).collect()
+        } else {
+            (0..self.sources.len()).collect()
+        };
+
+        self.files.clear();
+        self.searched = self.query.clone();
+        self.complete = true;
+        self.error = None;
+        self.selected = 0;
+        self.scroll = 0;
+        self.preview = None;
+        // an empty query would match every line
+        if self.query.is_empty() {
+            return;
+        }
+
+        self.config.query = self.query.clone();
+        self.config.patterns = vec![self.query.clone()];
+        // the preview is the context here
+        let searcher = match SearcherBuilder::from_config(&self.config).context(0).build() {
+            Ok(searcher) => searcher,
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            },
+        };
+
+        let mut hits = Hits { records: Vec::new(), left: MAX_HITS };
+        for source in candidates {
+            // a file that can't be read (or went away since the walk) is left out, as are binary
+            // files and any the index skipped, which there's nothing to show for
+            let opened = match self.sources[source] {
+                Source::Skipped(_) => None,
+                ref other => open_source(&self.config, other).ok().and_then(|opened| opened),
+            };
+            match opened {
+                Some((input, false)) => {
+                    let _ = input.search(&searcher, &mut hits);
+                },
+                _ => continue,
+            }
+
+            if !hits.records.is_empty() {
+                let records = mem::take(&mut hits.records);
+                self.files.push(FileHits { source, name: self.sources[source].name(), records });
+            }
+            if hits.left == 0 {
+                self.complete = false;
+                break;
+            }
+        }
+    }
+
+    // the screen as lines of text (with ANSI colors) for a terminal width columns wide and height
+    // lines high: the query and a count of hits, then the hits, then the preview
+    pub fn render(&mut self, width: usize, height: usize) -> Vec<String> {
+        let mut lines = Vec::with_capacity(height);
+        lines.push(self.prompt(width));
+
+        // the preview gets two fifths of the screen, when there's room for it to show anything
+        let preview_height = if height >= 12 { (height - 2) * 2 / 5 } else { 0 };
+        let list_height = height.saturating_sub(1 + if preview_height > 0 { preview_height + 1 } else { 0 });
+        self.page = list_height.max(1);
+
+        let rows = self.rows();
+        let selected = rows.iter().position(|row| matches!(*row, Row::Hit(_, _, hit) if hit == self.selected));
+        if let Some(selected) = selected {
+            // keep the selected hit on screen, along with its file's name above it if it's the first
+            let top = if selected > 0 && matches!(rows[selected - 1], Row::File(_)) { selected - 1 } else { selected };
+            if top < self.scroll {
+                self.scroll = top;
+            } else if selected >= self.scroll + list_height {
+                self.scroll = selected + 1 - list_height;
+            }
+        }
+
+        for row in rows.iter().skip(self.scroll).take(list_height) {
+            lines.push(match *row {
+                Row::File(file) => paint(NAME_COLOR, &truncate(&self.files[file].name, width)),
+                Row::Hit(file, record, hit) => {
+                    let record = &self.files[file].records[record];
+                    let number = format!("{:>6}: ", record.line_number);
+                    let text_width = width.saturating_sub(number.chars().count());
+                    if hit == self.selected {
+                        let text = highlight(&record.line, &record.spans, text_width, SELECTED);
+                        let padding = " ".repeat(text_width.saturating_sub(record.line.chars().count()));
+                        format!("{}{}{}{}{}", SELECTED, number, text, padding, RESET)
+                    } else {
+                        format!("{}{}", paint(NUMBER_COLOR, &number), highlight(&record.line, &record.spans, text_width, ""))
+                    }
+                },
+            });
+        }
+        while lines.len() < 1 + list_height {
+            lines.push(String::new());
+        }
+
+        if preview_height > 0 {
+            lines.extend(self.render_preview(width, preview_height + 1));
+        }
+        lines
+    }
+
+    // the column (from 1) the cursor goes in, at the end of the query
+    pub fn cursor(&self, width: usize) -> usize {
+        (3 + self.query.chars().count()).min(width)
+    }
+
+    // the query, with the number of hits (or what's wrong with the query) over on the right
+    fn prompt(&self, width: usize) -> String {
+        let prompt = truncate(&format!("> {}", self.query), width);
+        let (status, color) = match self.error {
+            Some(ref error) => (error.lines().next().unwrap_or("").to_string(), ERROR_COLOR),
+            None => {
+                let limited = if self.complete { "" } else { " (stopped there)" };
+                (format!("{} hits in {} files{}", self.hits(), self.files.len(), limited), NUMBER_COLOR)
+            },
+        };
+
+        let used = prompt.chars().count() + status.chars().count() + 1;
+        if used > width {
+            return prompt;
+        }
+        format!("{}{}{}", prompt, " ".repeat(width - used + 1), paint(color, &status))
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        let mut hit = 0;
+
+        for (file, hits) in self.files.iter().enumerate() {
+            rows.push(Row::File(file));
+            for record in 0..hits.records.len() {
+                rows.push(Row::Hit(file, record, hit));
+                hit += 1;
+            }
+        }
+        rows
+    }
+
+    // a line naming the selected hit and the lines of the file around it, height lines in all
+    fn render_preview(&mut self, width: usize, height: usize) -> Vec<String> {
+        let (file, record) = match self.hit(self.selected) {
+            Some(hit) => hit,
+            None => return vec![String::new(); height],
+        };
+        let (name, record) = (&self.files[file].name, &self.files[file].records[record]);
+
+        let title = format!("── {}:{} ", name, record.line_number);
+        let rule = "─".repeat(width.saturating_sub(title.chars().count()));
+        let mut lines = vec![truncate(&format!("{}{}", title, rule), width)];
+
+        let key = (self.selected, height);
+        if self.preview.as_ref().is_none_or(|preview| preview.key != key) {
+            let first = record.line_number.saturating_sub((height - 1) / 2).max(1);
+            let lines = read_lines(&self.config, &self.sources[self.files[file].source], first..first + height - 1);
+            self.preview = Some(Preview { key, lines });
+        }
+
+        let hit_lines = record.line_number..record.end_line_number + 1;
+        for &(line_number, ref text) in self.preview.iter().flat_map(|preview| &preview.lines) {
+            let number = format!("{:>6}  ", line_number);
+            let text_width = width.saturating_sub(number.chars().count());
+            if !hit_lines.contains(&line_number) {
+                lines.push(format!("{}{}", paint(NUMBER_COLOR, &number), highlight(text, &[], text_width, "")));
+                continue;
+            }
+            // a match on several lines (with -U) has its spans in all of them together
+            let spans = if record.end_line_number == record.line_number { record.spans.as_slice() } else { &[] };
+            lines.push(format!("{}{}", paint(SELECTED, &number), highlight(text, spans, text_width, "")));
+        }
+
+        lines.resize(height, String::new());
+        lines
+    }
+}
+
+// collects the records for the browser, stopping once it has as many as it has room for
+struct Hits {
+    records: Vec<Record>,
+    left: usize,
+}
+
+impl Sink for Hits {
+    fn matched(&mut self, record: &Record) -> io::Result<bool> {
+        self.records.push(record.clone());
+        self.left -= 1;
+        Ok(self.left > 0)
+    }
+}
+
+// the lines of source numbered in range, read (and decompressed and decoded) as a search would
+fn read_lines(config: &Config, source: &Source, range: Range<usize>) -> Vec<(usize, String)> {
+    let mut reader: Box<dyn BufRead> = match open_source(config, source) {
+        Ok(Some((Input::Map(map), _))) => Box::new(Cursor::new(map)),
+        Ok(Some((Input::Reader(reader), _))) => reader,
+        _ => return Vec::new(),
+    };
+
+    let mut lines = Vec::new();
+    let mut buf = Vec::new();
+    let mut line_number = 0;
+    while let Ok(Some((_, line))) = read_line(&mut reader, &mut buf) {
+        line_number += 1;
+        if line_number >= range.end {
+            break;
+        }
+        if line_number >= range.start {
+            lines.push((line_number, line.into_owned()));
+        }
+    }
+    lines
+}
+
+// text, cut down to width chars, with the bytes in spans colored as matches; base is the style
+// the rest of the text is in, put back after each match
+//
+// tabs and newlines (in -U matches) would throw out the layout, so they're shown as one char each
+fn highlight(text: &str, spans: &[Range<usize>], width: usize, base: &str) -> String {
+    let mut out = String::new();
+    let mut in_match = false;
+
+    for (index, c) in text.char_indices().take(width) {
+        let matched = spans.iter().any(|span| span.contains(&index));
+        if matched != in_match {
+            out.push_str(if matched { MATCH_COLOR } else { RESET });
+            if !matched {
+                out.push_str(base);
+            }
+            in_match = matched;
+        }
+        out.push(match c {
+            '\n' => '↵',
+            c if c.is_control() => ' ',
+            c => c,
+        });
+    }
+
+    if in_match {
+        out.push_str(RESET);
+        out.push_str(base);
+    }
+    out
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+// the command to open path at line in editor (the value of $EDITOR, which can have args of its
+// own, like "code --wait"): most editors take +LINE before the file, VS Code wants --goto
+// FILE:LINE and a few others FILE:LINE
+pub fn editor_command(editor: &str, path: &Path, line: usize) -> Vec<String> {
+    let mut command: Vec<String> = editor.split_whitespace().map(|arg| arg.to_string()).collect();
+    let program = command.first()
+        .and_then(|program| Path::new(program).file_name())
+        .map(|program| program.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = path.display().to_string();
+
+    match program.as_str() {
+        "code" | "code-insiders" | "codium" => {
+            command.push("--goto".to_string());
+            command.push(format!("{}:{}", path, line));
+        },
+        "subl" | "zed" | "hx" => command.push(format!("{}:{}", path, line)),
+        _ => {
+            command.push(format!("+{}", line));
+            command.push(path);
+        },
+    }
+    command
+}
+
+// the keys in bytes read from a terminal in raw mode, where arrows and the like come as escape
+// sequences; an escape on its own is the Esc key, anything not understood is left out
+pub fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = text.chars();
+    let mut keys = Vec::new();
+
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' => match chars.next() {
+                None => Key::Esc,
+                // parameters, then a final byte from @ to ~
+                Some('[') | Some('O') => {
+                    let mut sequence = String::new();
+                    for c in chars.by_ref() {
+                        sequence.push(c);
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                    match sequence.as_str() {
+                        "A" => Key::Up,
+                        "B" => Key::Down,
+                        "H" | "1~" => Key::Home,
+                        "F" | "4~" => Key::End,
+                        "5~" => Key::PageUp,
+                        "6~" => Key::PageDown,
+                        _ => continue,
+                    }
+                },
+                // alt with a key
+                Some(_) => continue,
+            },
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            c if (c as u32) < 0x20 => Key::Ctrl((c as u8 + b'a' - 1) as char),
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+// runs the browser in the terminal until it's quit, returning whether the last query had hits
+#[cfg(unix)]
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    let mut browser = Browser::new(config)?;
+    let mut terminal = terminal::Terminal::open()?;
+    let mut drawn = None;
+
+    loop {
+        // redrawn after every key, and whenever the terminal changes size
+        let size = terminal.size();
+        if drawn != Some(size) {
+            let (width, height) = size;
+            terminal.draw(&browser.render(width, height), browser.cursor(width))?;
+            drawn = Some(size);
+        }
+
+        for key in terminal.keys()? {
+            drawn = None;
+            match browser.key(key) {
+                Action::Redraw => {},
+                Action::Quit => return Ok(browser.hits() > 0),
+                Action::Open(path, line) => {
+                    let editor = env::var("EDITOR").ok().filter(|editor| !editor.trim().is_empty());
+                    let command = editor_command(editor.as_deref().unwrap_or("vi"), &path, line);
+                    if let Err(e) = terminal.suspend(&command) {
+                        browser.error = Some(format!("couldn't run {}: {}", command[0], e));
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn run(_config: Config) -> Result<bool, Box<dyn Error>> {
+    Err("--interactive needs a Unix terminal".into())
+}
+
+// the terminal, through /dev/tty so it works whatever stdin and stdout are; in raw mode keys
+// arrive as they're pressed, and the alternate screen leaves the shell's scrollback alone
+#[cfg(unix)]
+mod terminal {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Write};
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use std::process::{Command, Stdio};
+
+    use libc;
+
+    use super::{parse_keys, Key};
+
+    pub struct Terminal {
+        tty: File,
+        // the settings to put back when we're done
+        original: libc::termios,
+    }
+
+    impl Terminal {
+        pub fn open() -> io::Result<Terminal> {
+            let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+            let mut original: libc::termios = unsafe { mem::zeroed() };
+            if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut original) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut terminal = Terminal { tty, original };
+            terminal.enter()?;
+            Ok(terminal)
+        }
+
+        fn enter(&mut self) -> io::Result<()> {
+            let mut raw = self.original;
+            unsafe { libc::cfmakeraw(&mut raw) };
+            // reads wait a tenth of a second at most, so an escape on its own can be told from
+            // the start of an arrow key, and a resize gets noticed
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            if unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &raw) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.tty.write_all(b"\x1b[?1049h")
+        }
+
+        fn leave(&mut self) -> io::Result<()> {
+            self.tty.write_all(b"\x1b[?1049l\x1b[?25h")?;
+            if unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.original) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        // columns and lines, or 80x24 if the terminal won't say
+        pub fn size(&self) -> (usize, usize) {
+            let mut size: libc::winsize = unsafe { mem::zeroed() };
+            let found = unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } == 0;
+            if found && size.ws_col > 0 && size.ws_row > 0 {
+                (size.ws_col as usize, size.ws_row as usize)
+            } else {
+                (80, 24)
+            }
+        }
+
+        // the keys pressed since last time, waiting a little for one if there aren't any
+        pub fn keys(&mut self) -> io::Result<Vec<Key>> {
+            let mut buf = [0; 64];
+            match self.tty.read(&mut buf) {
+                Ok(read) => Ok(parse_keys(&buf[..read])),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => Ok(Vec::new()),
+                Err(e) => Err(e),
+            }
+        }
+
+        // draws lines over the whole screen, clearing what was there, then puts the cursor in
+        // column of the first line
+        pub fn draw(&mut self, lines: &[String], column: usize) -> io::Result<()> {
+            let mut screen = String::from("\x1b[?25l\x1b[H");
+            screen.push_str(&lines.join("\x1b[K\r\n"));
+            screen.push_str(&format!("\x1b[K\x1b[J\x1b[1;{}H\x1b[?25h", column));
+            self.tty.write_all(screen.as_bytes())?;
+            self.tty.flush()
+        }
+
+        // hands the terminal over to command (an editor) until it exits
+        pub fn suspend(&mut self, command: &[String]) -> io::Result<()> {
+            self.leave()?;
+            let status = Command::new(&command[0])
+                .args(&command[1..])
+                .stdin(Stdio::from(self.tty.try_clone()?))
+                .stdout(Stdio::from(self.tty.try_clone()?))
+                .stderr(Stdio::from(self.tty.try_clone()?))
+                .status();
+            self.enter()?;
+            status.map(|_| ())
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            let _ = self.leave();
+        }
+    }
+}
@@ -1,73 +1,386 @@
+extern crate bzip2;
+extern crate flate2;
+extern crate globset;
+extern crate ignore;
+#[cfg(unix)]
+extern crate libc;
+extern crate memchr;
+extern crate memmap2;
+extern crate regex;
+extern crate regex_syntax;
+extern crate same_file;
+extern crate xz2;
+extern crate zstd;
+
+use std::borrow::Cow;
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::env;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use fold::FoldedQuery;
+use memmap2::Mmap;
+use regex::RegexBuilder;
+
+mod config;
+mod decompress;
+pub mod encoding;
+pub mod fold;
+mod follow;
+pub mod fuzzy;
+mod index;
+pub mod interactive;
+pub mod matcher;
+mod mmap;
+pub mod multi;
+mod output;
+mod parallel;
+mod replace;
+pub mod searcher;
+pub mod walk;
+
+pub use config::{ColorChoice, Config, ConfigError, OutputMode, USAGE, VERSION};
+pub use encoding::Encoding;
+pub use follow::Follow;
+pub use matcher::{Boundary, Case, Matcher};
+pub use searcher::{Record, RecordKind, Records, Searcher, SearcherBuilder, Sink};
+use output::Printer;
 
+// a filename of "-" (or no filenames at all) means read from stdin
+pub const STDIN: &str = "-";
+const STDIN_NAME: &str = "(standard input)";
+
+// somewhere to read lines from
 #[derive(Debug)]
-pub struct Config {
-    pub query: String,
-    pub filename: String,
-    pub case_sensitive: bool
-}
-
-impl Config {
-    // returns a Result<T, E>, OK gets unwrapped and Err is handled
-    // i.e. Result<OK(_), Err(e)>
-    // takes a slice of Strings returns a Result with Config
-    pub fn new(args: &[String]) -> Result<Config, String> {
-        if args.len() < 3 {
-            let message = format!("usage: {} query filename [-ci]", args[0]);
-            return Err(message);
+pub enum Source {
+    Stdin,
+    // a file named on the command line
+    File(PathBuf),
+    // a file found by walking a directory, skipped if it turns out to be binary (unless --text)
+    Walked(PathBuf),
+    // a file found by walking a directory that the --index index says can't match, so it's never
+    // opened, only counted as searched
+    Skipped(PathBuf),
+}
+
+impl Source {
+    // the name printed before matches
+    pub fn name(&self) -> String {
+        match *self {
+            Source::Stdin => STDIN_NAME.to_string(),
+            Source::File(ref path) | Source::Walked(ref path) | Source::Skipped(ref path) => {
+                path.display().to_string()
+            },
         }
+    }
+}
 
-        // using a shadowed iterator, and skip first arg
-        let mut args = args.iter();
-        args.next();
+// Box<dyn Error> return a type that implements Error trait, but we don't have to say which type
+// the return value will be; remember ? will return early, any error from the current function
+//
+// returns Ok(true) if any line was selected, so main can exit with grep's status codes
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    if config.build_index {
+        let mut failed = false;
+        for dir in &config.filenames {
+            let mut errors = Vec::new();
+            let stats = index::build(Path::new(dir), &config, &mut errors)?;
+            warn(&errors);
+            failed |= !errors.is_empty();
+            println!("indexed {} files in {} ({} read, {} unchanged, {} removed)", stats.files, dir,
+                     stats.read, stats.files - stats.read, stats.removed);
+        }
+        if failed {
+            return Err(Incomplete.into());
+        }
+        return Ok(true);
+    }
 
-        // clone was needed here, when we passed `&args` (a slice with String elements in the
-        // parameter args) but this new function didn't own args
-        // remember: slice is a list of consectutive references in memory
-        // let args_length = args.len();
-        // let query = args[args_length-2].clone();
-        // let filename = args[args_length-1].clone();
+    if config.interactive {
+        return interactive::run(config);
+    }
 
-        let query = match args.next() {
-            Some(arg) => arg.to_string(),
-            None => return Err("Didn't get a query string".to_string()),
-        };
+    // compiled once up front, so a bad pattern is reported before anything is read
+    let searcher = SearcherBuilder::from_config(&config).build()?;
 
-        let filename = match args.next() {
-            Some(arg) => arg.to_string(),
-            None => return Err("Didn't get a file name".to_string()),
-        };
+    // only prefix matches with their filename when there's more than one source to tell apart
+    let prefix = config.filenames.len() > 1 ||
+        config.filenames.iter().any(|filename| Path::new(filename).is_dir());
+    let mut errors = Vec::new();
+    let sources = sources(&config, &mut errors)?;
+    warn(&errors);
 
-        // if flag set, we'll be case sensitive, otherwise will take from env var being set
-        let case_sensitive = { args.next() == Some(&"-c".to_string()) } || !env::var("CASE_SENSITIVE").is_err();
+    if config.follow {
+        return follow::run(&config, &searcher);
+    }
+    let quiet = config.mode == OutputMode::Quiet;
+    let selected = if config.in_place {
+        replace::run(&config, searcher.matcher(), &sources)?
+    } else if config.threads > 1 && sources.len() > 1 {
+        parallel::run(config, searcher, prefix, sources)?
+    } else {
+        search_sources(&config, &searcher, prefix, &sources)?
+    };
 
-        // OK(Config { query: query, filename: filename }) would also work here
-        Ok(Config { query, filename, case_sensitive })
+    // with -q a selected line is the answer, whatever couldn't be read
+    let answered = quiet && selected;
+    if !errors.is_empty() && !answered {
+        return Err(Incomplete.into());
     }
+    Ok(selected)
 }
 
-// Box<Error> return a type that implements Error trait, but we don't have to say which type the
-// return value will be; remember ? will return early, any error from the current function
-pub fn run(config: Config) -> Result<(), Box<Error>> {
-    let mut f = File::open(config.filename)?;
+// searches each source in turn, printing as it goes
+fn search_sources(config: &Config, searcher: &Searcher, prefix: bool, sources: &[Source])
+    -> Result<bool, Box<dyn Error>> {
+    let stdout = io::stdout();
+    let mut printer = Printer::new(config, prefix, stdout.lock());
+    let mut selected = false;
 
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
+    let mut failed = false;
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
-    } else {
-        search_case_insensitive(&config.query, &contents)
+    for source in sources {
+        match search_source(config, searcher, &mut printer, source)? {
+            Ok(count) => selected |= count > 0,
+            Err(e) => {
+                report(&source.name(), &e);
+                failed = true;
+            },
+        }
+
+        // with -q the answer is known as soon as anything is selected
+        if selected && config.mode == OutputMode::Quiet {
+            return Ok(true);
+        }
+    }
+    printer.summary()?;
+
+    if failed {
+        return Err(Incomplete.into());
+    }
+    Ok(selected)
+}
+
+// what run ends with when it carried on past something it couldn't read, like a missing file or a
+// directory without permission to list it; that's already been reported on stderr, so main only
+// has to exit with grep's status for an error
+#[derive(Debug)]
+pub struct Incomplete;
+
+impl fmt::Display for Incomplete {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "some files couldn't be searched")
+    }
+}
+
+impl Error for Incomplete {}
+
+// a source that couldn't be searched, reported like grep does, for the rest to be searched anyway
+fn report(name: &str, error: &dyn Error) {
+    eprintln!("minigrep: {}: {}", name, error);
+}
+
+// like grep -r, the parts of a tree the walk couldn't read are reported and the rest searched
+fn warn(errors: &[ignore::Error]) {
+    for error in errors {
+        // the walker's own message names the path twice, as "dir: IO error for operation on dir: ...",
+        // so the path goes with the io::Error at the bottom of it
+        match (error_path(error), error.io_error()) {
+            (Some(path), Some(e)) => {
+                let mut cause: &dyn Error = e;
+                while let Some(source) = cause.source() {
+                    cause = source;
+                }
+                report(&path.display().to_string(), cause);
+            },
+            _ => eprintln!("minigrep: {}", error),
+        }
+    }
+}
+
+fn error_path(error: &ignore::Error) -> Option<&Path> {
+    match *error {
+        ignore::Error::WithPath { ref path, .. } => Some(path),
+        ignore::Error::WithDepth { ref err, .. } => error_path(err),
+        _ => None,
+    }
+}
+
+// every source named in the config, with directories walked recursively for the files in them;
+// anything in a directory that couldn't be read is left out and added to errors
+pub fn sources(config: &Config, errors: &mut Vec<ignore::Error>) -> Result<Vec<Source>, Box<dyn Error>> {
+    let mut sources = Vec::new();
+
+    for filename in &config.filenames {
+        let path = Path::new(filename);
+
+        if filename == STDIN {
+            sources.push(Source::Stdin);
+        } else if path.is_dir() {
+            let filter = if config.index { Some(index::Filter::new(path, config)?) } else { None };
+
+            for file in walk::files(path, config, errors)? {
+                match filter.as_ref().and_then(|filter| filter.rules_out(path, &file)) {
+                    // a binary file would be skipped without a word anyway
+                    Some(binary) if binary && !config.text => {},
+                    Some(_) => sources.push(Source::Skipped(file)),
+                    None => sources.push(Source::Walked(file)),
+                }
+            }
+        } else {
+            sources.push(Source::File(path.to_path_buf()));
+        }
+    }
+
+    if config.sort_files {
+        // sort_by_key needs a key it can own, so we sort on the printed name
+        sources.sort_by_key(|source| source.name());
+    }
+
+    Ok(sources)
+}
+
+// opens a source and searches it, with the printer as the sink, returning how many lines were
+// selected; a source that can't be opened (say it's missing) or fails part way through reading
+// (like a truncated .gz) gives back why inside the Ok, for the caller to report before carrying on
+// with the next, the outer Err is for anything else, like failing to write the output
+fn search_source<W: Write>(config: &Config, searcher: &Searcher, printer: &mut Printer<W>, source: &Source)
+    -> io::Result<io::Result<usize>> {
+    let name = source.name();
+    if let Source::Skipped(_) = *source {
+        return print_source(printer, &name, false, |_| Ok(0)).map(Ok);
+    }
+
+    match open_source(config, source) {
+        Ok(Some((input, binary))) => {
+            match print_source(printer, &name, binary, |printer| input.search(searcher, printer)) {
+                Ok(count) => Ok(Ok(count)),
+                Err(e) if ReadError::is(&e) => Ok(Err(e)),
+                Err(e) => Err(e),
+            }
+        },
+        Ok(None) => Ok(Ok(0)),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+// an error reading a source, wrapped so it can be told apart from an error writing the output
+// once both have come back out of a search
+#[derive(Debug)]
+struct ReadError(io::Error);
+
+impl ReadError {
+    fn wrap(e: io::Error) -> io::Error {
+        io::Error::new(e.kind(), ReadError(e))
+    }
+
+    fn is(e: &io::Error) -> bool {
+        e.get_ref().is_some_and(|inner| inner.is::<ReadError>())
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+// a reader whose errors are all ReadErrors
+struct Reading<R>(R);
+
+impl<R: Read> Read for Reading<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(ReadError::wrap)
+    }
+}
+
+impl<R: BufRead> BufRead for Reading<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf().map_err(ReadError::wrap)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.0.consume(amount)
+    }
+}
+
+// a source opened for searching
+enum Input {
+    // a big file, searched where it is
+    Map(Mmap),
+    // a Box<dyn BufRead> lets stdin, files and decompressors all be read the same way
+    Reader(Box<dyn BufRead>),
+}
+
+impl Input {
+    fn search<S: Sink>(self, searcher: &Searcher, sink: &mut S) -> io::Result<usize> {
+        match self {
+            Input::Map(map) => searcher.search_slice(&map, sink),
+            Input::Reader(reader) => searcher.search_sink(reader, sink),
+        }
+    }
+}
+
+// opens a source (decompressing and decoding it as needed) along with whether it's binary, or None
+// for a binary file found by walking a directory, which is skipped
+fn open_source(config: &Config, source: &Source) -> io::Result<Option<(Input, bool)>> {
+    // files found by walking a directory are skipped if they turn out to be binary, others only
+    // print whether they matched
+    let walked = matches!(*source, Source::Walked(_));
+    let binary = |bytes: &[u8]| !config.text && walk::is_binary(bytes);
+
+    // files are decompressed whenever their magic bytes say they're compressed, whatever they're
+    // called, but stdin only with -z
+    let (reader, sniff): (Box<dyn BufRead>, bool) = match *source {
+        Source::Stdin => (Box::new(io::stdin().lock()), config.search_zip),
+        Source::File(ref path) | Source::Walked(ref path) | Source::Skipped(ref path) => {
+            let file = File::open(path)?;
+
+            // big files that don't need decompressing or decoding are searched where they are,
+            // through a memory map, rather than being read in
+            let map = if config.mmap { mmap::map(&file) } else { None };
+            let plain = |map: &Mmap| decompress::detect(map).is_none() && encoding::is_utf8(config.encoding, map);
+            if let Some(map) = map.filter(plain) {
+                let binary = binary(&map);
+                if walked && binary {
+                    return Ok(None);
+                }
+                return Ok(Some((Input::Map(map), binary)));
+            }
+
+            (Box::new(BufReader::new(file)), true)
+        },
     };
 
-    for line in results {
-        println!("{}", line);
+    let reader = if sniff { decompress::decompress(reader)? } else { reader };
+    // UTF-16 is full of NUL bytes, so it's decoded before checking for binary
+    let mut reader: Box<dyn BufRead> = Box::new(Reading(encoding::decode(reader, config.encoding)?));
+
+    // fill_buf peeks at the start of the file (after decompressing) without consuming it
+    let binary = binary(reader.fill_buf()?);
+    if walked && binary {
+        return Ok(None);
     }
+    Ok(Some((Input::Reader(reader), binary)))
+}
+
+// runs a search with the printer as its sink, between starting and finishing the source
+fn print_source<W, F>(printer: &mut Printer<W>, name: &str, binary: bool, search: F) -> io::Result<usize>
+    where W: Write, F: FnOnce(&mut Printer<W>) -> io::Result<usize> {
+    printer.start(name, binary)?;
+    let count = search(printer)?;
+    printer.finish(count)?;
 
-    Ok(())
+    Ok(count)
 }
 
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
@@ -87,15 +400,77 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
 }
 
 pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    // shadowed variable with the same name
-    let query = query.to_lowercase(); // to_lowercase returns a String (not string slice)
-    let mut results = Vec::new();
+    // shadowed variable with the same name, the query is case folded once and each line is folded
+    // a char at a time while comparing, so no new String is made per line
+    let query = FoldedQuery::new(query);
 
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
-        }
+    contents.lines()
+        .filter(|line| query.find(line).is_some())
+        .collect()
+
+    // this used to lowercase every line, allocating a String for each one (and missing folds like
+    // ß to SS), see benches/case_insensitive.rs for how the two compare
+    // let query = query.to_lowercase(); // to_lowercase returns a String (not string slice)
+    // let mut results = Vec::new();
+    // for line in contents.lines() {
+    //     if line.to_lowercase().contains(&query) {
+    //         results.push(line);
+    //     }
+    // }
+    // results
+}
+
+// the query is compiled as a regular expression and matched against each line, so anchors like
+// ^ and $ apply to the start and end of a line; an invalid pattern is returned as a regex::Error
+pub fn search_regex<'a>(query: &str, contents: &'a str, case_sensitive: bool)
+    -> Result<Vec<&'a str>, regex::Error> {
+    let re = RegexBuilder::new(query)
+        .case_insensitive(!case_sensitive)
+        .build()?;
+
+    Ok(contents.lines()
+        .filter(|line| re.is_match(line))
+        .collect())
+}
+
+// only lines where the query is a whole word, so duct matches "Duct tape" but not "productive";
+// word chars are Unicode letters, digits, marks and connectors (see matcher::is_word_char)
+pub fn search_words<'a>(query: &str, contents: &'a str, case_sensitive: bool) -> Vec<&'a str> {
+    search_bounded(query, contents, case_sensitive, Boundary::Word)
+}
+
+// only lines that are exactly the query
+pub fn search_whole_lines<'a>(query: &str, contents: &'a str, case_sensitive: bool) -> Vec<&'a str> {
+    search_bounded(query, contents, case_sensitive, Boundary::Line)
+}
+
+fn search_bounded<'a>(query: &str, contents: &'a str, case_sensitive: bool, boundary: Boundary)
+    -> Vec<&'a str> {
+    // a literal query can't be an invalid regex, so this can't fail
+    let matcher = Matcher::with_boundary(&[query.to_string()], case_sensitive, false, boundary)
+        .expect("literal patterns always compile");
+
+    contents.lines()
+        .filter(|line| matcher.find(line).is_some())
+        .collect()
+}
+
+// reads the next line into buf, giving back how many bytes were read and the line without its
+// line ending, or None at the end; bytes that aren't valid UTF-8 are replaced with U+FFFD so one
+// bad line can't fail the search
+fn read_line<'b, R: BufRead>(reader: &mut R, buf: &'b mut Vec<u8>) -> io::Result<Option<(usize, Cow<'b, str>)>> {
+    buf.clear();
+    let read = reader.read_until(b'\n', buf)?;
+    if read == 0 {
+        return Ok(None);
     }
 
-    results
+    Ok(Some((read, line_text(buf))))
+}
+
+// a line's bytes as text, without the line ending
+fn line_text(line: &[u8]) -> Cow<'_, str> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line)
 }
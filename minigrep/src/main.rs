@@ -2,7 +2,7 @@ extern crate minigrep;
 
 use std::env;
 use std::process;
-use minigrep::Config;
+use minigrep::{Config, ConfigError, Incomplete};
 
 fn main() {
     // passing &args (slice of String references)
@@ -14,24 +14,39 @@ fn main() {
 
     // skip first arg and collect as Vec
     // see http://tinyurl.com/y77cwx3e for tip
-    let args = env::args().collect::<Vec<_>>();
+    let args = env::args().skip(1).collect::<Vec<_>>();
 
-    let config = Config::new(&args).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
+    // flags from the config file (if there is one) go first, so the command line overrides them;
+    // --help, --version and --type-list come back as errors too, but they print to stdout and
+    // exit cleanly
+    let config = Config::from_env(&args).unwrap_or_else(|err| {
+        match err {
+            ConfigError::Help | ConfigError::Version | ConfigError::TypeList => {
+                println!("{}", err);
+                process::exit(0);
+            },
+            _ => {
+                eprintln!("Problem parsing arguments: {}", err);
+                eprintln!("Try 'minigrep --help' for more information.");
+                process::exit(2);
+            },
+        }
     });
 
-    // handle error with match like so
-    // match run(config) {
-    //     Err(e) => {
-    //         println!("Application error: {}", e);
-    //         process::exit(1);
-    //     },
-    //     _ => { }
+    // handle error with the if let syntax like so
+    // if let Err(e) = minigrep::run(config) {
+    //     eprintln!("Application error: {}", e);
+    //     process::exit(1);
     // }
-    // or use the if let syntax
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {}", e);
-        process::exit(1);
+    // or use match, to exit like grep does: 0 for a match, 1 for no match and 2 for an error
+    match minigrep::run(config) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        // what couldn't be searched has been reported already
+        Err(ref e) if e.is::<Incomplete>() => process::exit(2),
+        Err(e) => {
+            eprintln!("Application error: {}", e);
+            process::exit(2);
+        },
     }
 }
@@ -0,0 +1,358 @@
+use std::ops::Range;
+
+use regex::{self, Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+
+use fold::{self, FoldedQuery};
+use fuzzy::FuzzyQuery;
+use multi::MultiPattern;
+use Config;
+
+// where in a line a match has to be to count
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Boundary {
+    // anywhere at all, the default
+    Anywhere,
+    // a whole word, with no word chars right before or after it (-w)
+    Word,
+    // the whole line (-x)
+    Line,
+}
+
+// whether letter case matters
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Case {
+    Sensitive,
+    Insensitive,
+    // insensitive, unless a pattern has an uppercase letter in it (-S)
+    Smart,
+}
+
+// finds a query (or any of several patterns) in a single line
+#[derive(Debug)]
+pub struct Matcher {
+    kind: Kind,
+    boundary: Boundary,
+}
+
+// the different ways the patterns can be matched
+#[derive(Debug)]
+enum Kind {
+    Literal(String),
+    // a case insensitive literal, compared using Unicode case folding
+    Folded(FoldedQuery),
+    Regex(Regex),
+    // several literal patterns, found in one pass with an Aho-Corasick automaton
+    Multi(MultiPattern),
+    // several regex patterns, the set says which matched a line in one pass and the matching
+    // regexes then find where
+    RegexSet(RegexSet, Vec<Regex>),
+    // the query, give or take a few edits (boxed, as its lookup tables make it much bigger than
+    // the others)
+    Fuzzy(Box<FuzzyQuery>),
+}
+
+// a match, the pattern it was for and (with --fuzzy) how many edits away from it the text is
+type Found = (usize, Range<usize>, usize);
+
+impl Matcher {
+    pub fn new(query: &str, case_sensitive: bool, regex: bool) -> Result<Matcher, regex::Error> {
+        Matcher::with_patterns(&[query.to_string()], case_sensitive, regex)
+    }
+
+    // a matcher for any of the patterns, a line matches if one or more of them do
+    pub fn with_patterns(patterns: &[String], case_sensitive: bool, regex: bool)
+        -> Result<Matcher, regex::Error> {
+        Matcher::with_boundary(patterns, case_sensitive, regex, Boundary::Anywhere)
+    }
+
+    // like with_patterns, but a match only counts if it's a whole word or the whole line
+    pub fn with_boundary(patterns: &[String], case_sensitive: bool, regex: bool, boundary: Boundary)
+        -> Result<Matcher, regex::Error> {
+        Matcher::build(patterns, case_sensitive, regex, boundary, false)
+    }
+
+    // multiline is for searching a whole file at once (-U) rather than a line at a time, so regex
+    // anchors like ^ and $ match at the start and end of every line (CRLF ones too)
+    fn build(patterns: &[String], case_sensitive: bool, regex: bool, boundary: Boundary, multiline: bool)
+        -> Result<Matcher, regex::Error> {
+        // a regex is anchored to the whole line (or the word) itself, which lets the regex engine
+        // try the longer alternatives too, so a|ab still matches the line or word "ab"; for -w the
+        // half boundaries only look outside the match, unlike \b, so c\+\+ still finds "c++ code"
+        let anchored: Vec<String>;
+        let patterns = match boundary {
+            Boundary::Line if regex => {
+                anchored = patterns.iter().map(|pattern| format!("^(?:{})$", pattern)).collect();
+                &anchored
+            },
+            Boundary::Word if regex => {
+                anchored = patterns.iter()
+                    .map(|pattern| format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern))
+                    .collect();
+                &anchored
+            },
+            _ => patterns,
+        };
+
+        let kind = match (patterns.len(), regex) {
+            (1, false) if case_sensitive => Kind::Literal(patterns[0].clone()),
+            (1, false) => Kind::Folded(FoldedQuery::new(&patterns[0])),
+            (1, true) => {
+                let re = RegexBuilder::new(&patterns[0])
+                    .case_insensitive(!case_sensitive)
+                    .multi_line(multiline)
+                    .crlf(multiline)
+                    .build()?;
+                Kind::Regex(re)
+            },
+            (_, false) => Kind::Multi(MultiPattern::new(patterns, case_sensitive)),
+            (_, true) => {
+                // collect can gather an iterator of Results into a Result of a Vec
+                let regexes = patterns.iter()
+                    .map(|pattern| {
+                        RegexBuilder::new(pattern)
+                            .case_insensitive(!case_sensitive)
+                            .multi_line(multiline)
+                            .crlf(multiline)
+                            .build()
+                    })
+                    .collect::<Result<Vec<Regex>, regex::Error>>()?;
+                let set = RegexSetBuilder::new(patterns)
+                    .case_insensitive(!case_sensitive)
+                    .multi_line(multiline)
+                    .crlf(multiline)
+                    .build()?;
+                Kind::RegexSet(set, regexes)
+            },
+        };
+
+        Ok(Matcher { kind, boundary })
+    }
+
+    // a matcher for text within max_distance edits of query (see FuzzyQuery)
+    pub fn fuzzy(query: &str, max_distance: usize, case_sensitive: bool, boundary: Boundary)
+        -> Result<Matcher, String> {
+        let query = FuzzyQuery::new(query, max_distance, case_sensitive)?;
+        Ok(Matcher { kind: Kind::Fuzzy(Box::new(query)), boundary })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Matcher, String> {
+        let case = match (config.smart_case, config.case_sensitive) {
+            (true, _) => Case::Smart,
+            (false, true) => Case::Sensitive,
+            (false, false) => Case::Insensitive,
+        };
+        Matcher::from_options(&config.patterns, case, config.regex, config.boundary, config.fuzzy, config.multiline)
+    }
+
+    // a matcher for every option that changes what matches, with an error message naming the
+    // pattern that's wrong; fuzzy matching only uses the first pattern
+    pub fn from_options(patterns: &[String], case: Case, regex: bool, boundary: Boundary, fuzzy: Option<usize>,
+                        multiline: bool) -> Result<Matcher, String> {
+        // with smart case, an uppercase letter in any pattern turns case sensitivity back on
+        let case_sensitive = match case {
+            Case::Sensitive => true,
+            Case::Insensitive => false,
+            Case::Smart => patterns.iter().any(|pattern| fold::has_uppercase(pattern, regex)),
+        };
+
+        if let Some(max_distance) = fuzzy {
+            let query = patterns.first().map_or("", |query| query.as_str());
+            return Matcher::fuzzy(query, max_distance, case_sensitive, boundary);
+        }
+
+        Matcher::build(patterns, case_sensitive, regex, boundary, multiline).map_err(|e| {
+            // name the pattern that's wrong when there's more than one
+            let pattern = patterns.iter()
+                .find(|pattern| Regex::new(pattern).is_err())
+                .or(patterns.first())
+                .map_or("", |pattern| pattern.as_str());
+            format!("Invalid pattern '{}': {}", pattern, e)
+        })
+    }
+
+    // the byte range of the first match in the line, if there is one
+    pub fn find(&self, line: &str) -> Option<Range<usize>> {
+        self.find_pattern(line).map(|(_, span)| span)
+    }
+
+    // like find, but also gives the index of the pattern that matched
+    pub fn find_pattern(&self, line: &str) -> Option<(usize, Range<usize>)> {
+        self.find_bounded(line, 0).map(|(pattern, span, _)| (pattern, span))
+    }
+
+    // like find_pattern, but also gives the edit distance between the match and the query, which
+    // is only ever more than 0 with --fuzzy
+    pub fn find_match(&self, line: &str) -> Option<(usize, Range<usize>, usize)> {
+        self.find_bounded(line, 0)
+    }
+
+    // whether this is a --fuzzy matcher, so matches have a distance worth showing
+    pub fn is_fuzzy(&self) -> bool {
+        matches!(self.kind, Kind::Fuzzy(_))
+    }
+
+    // the byte ranges of every non-overlapping match in the line, skipping empty ones (a regex
+    // like ^ matches without covering any text)
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        self.find_iter(line).into_iter()
+            .map(|(_, span, _)| span)
+            .filter(|span| !span.is_empty())
+            .collect()
+    }
+
+    // every match in text with the index of its pattern, including empty ones (which still select
+    // the line they're on), for -U to work out which lines each match covers
+    pub fn find_patterns(&self, text: &str) -> Vec<(usize, Range<usize>)> {
+        self.find_iter(text).into_iter().map(|(pattern, span, _)| (pattern, span)).collect()
+    }
+
+    // every match in the line replaced with replacement, which in regex mode can refer to capture
+    // groups as $1 or ${name} ($$ for a plain $), along with where each replacement ended up in
+    // the new line so it can be highlighted
+    pub fn replace(&self, line: &str, replacement: &str) -> (String, Vec<Range<usize>>) {
+        let mut out = String::new();
+        let mut spans = Vec::new();
+        let mut last = 0;
+
+        for (pattern, span, _) in self.find_iter(line) {
+            out.push_str(&line[last..span.start]);
+            let start = out.len();
+
+            let re = match self.kind {
+                Kind::Regex(ref re) => Some(re),
+                Kind::RegexSet(_, ref regexes) => Some(&regexes[pattern]),
+                _ => None,
+            };
+            // captures_at finds the same match again, this time keeping track of the groups
+            match re.and_then(|re| re.captures_at(line, span.start)) {
+                Some(captures) => captures.expand(replacement, &mut out),
+                None => out.push_str(replacement),
+            }
+
+            spans.push(start..out.len());
+            last = span.end;
+        }
+        out.push_str(&line[last..]);
+
+        (out, spans)
+    }
+
+    // every non-overlapping match in the line, including empty ones, except (like the regex
+    // crate) an empty match right where the last match ended, so a* replaces "aa" only once
+    //
+    // with --fuzzy there's only the best match, as lots of text is a few edits from a short query
+    fn find_iter(&self, line: &str) -> Vec<Found> {
+        if let Kind::Fuzzy(_) = self.kind {
+            return self.find_bounded(line, 0).into_iter().collect();
+        }
+
+        let mut matches = Vec::new();
+        let mut from = 0;
+        let mut last_end = None;
+
+        while from <= line.len() {
+            let (pattern, span, distance) = match self.find_bounded(line, from) {
+                Some(found) => found,
+                None => break,
+            };
+
+            if span.is_empty() {
+                from = next_char(line, span.end);
+                if last_end == Some(span.start) {
+                    continue;
+                }
+            } else {
+                from = span.end;
+            }
+            last_end = Some(span.end);
+            matches.push((pattern, span, distance));
+        }
+
+        matches
+    }
+
+    // the first match starting at or after byte from that's on the boundary; when one isn't,
+    // the search carries on from the char after where it started, so -w still finds the duct in
+    // "productive duct"
+    fn find_bounded(&self, line: &str, mut from: usize) -> Option<Found> {
+        while from <= line.len() {
+            let (pattern, span, distance) = self.find_from(line, from)?;
+
+            let on_boundary = match self.boundary {
+                Boundary::Anywhere => true,
+                Boundary::Word => {
+                    !line[..span.start].chars().next_back().is_some_and(is_word_char) &&
+                        !line[span.end..].chars().next().is_some_and(is_word_char)
+                },
+                // with -U, line is a whole file, so it's lines within it
+                Boundary::Line => {
+                    (span.start == 0 || line[..span.start].ends_with('\n')) &&
+                        (span.end == line.len() || line[span.end..].starts_with('\n') ||
+                            line[span.end..].starts_with("\r\n"))
+                },
+            };
+            if on_boundary {
+                return Some((pattern, span, distance));
+            }
+
+            from = next_char(line, span.start);
+        }
+
+        None
+    }
+
+    // the leftmost match starting at or after byte from (the closest, with --fuzzy)
+    fn find_from(&self, line: &str, from: usize) -> Option<Found> {
+        match self.kind {
+            Kind::Literal(ref query) => {
+                line[from..].find(query.as_str()).map(|start| (0, from + start..from + start + query.len(), 0))
+            },
+            Kind::Folded(ref query) => query.find_from(line, from).map(|span| (0, span, 0)),
+            // find_at rather than slicing the line, so anchors like ^ and \b still see the text
+            // before from
+            Kind::Regex(ref re) => re.find_at(line, from).map(|m| (0, m.start()..m.end(), 0)),
+            Kind::Multi(ref multi) => multi.find_from(line, from).map(|m| (m.pattern, m.span, 0)),
+            Kind::RegexSet(ref set, ref regexes) => {
+                // of the patterns that matched, the one matching furthest left (and longest) wins
+                set.matches(line).into_iter()
+                    .filter_map(|pattern| regexes[pattern].find_at(line, from).map(|m| (pattern, m.start()..m.end(), 0)))
+                    .min_by_key(|(_, span, _)| (span.start, usize::MAX - span.end))
+            },
+            Kind::Fuzzy(ref query) => {
+                // with -w and -x it's whole words or the whole line that are compared to the query,
+                // rather than the closest part of the line being trimmed down to fit
+                let found = match self.boundary {
+                    Boundary::Anywhere => query.find_from(line, from),
+                    Boundary::Word => query.find_word_from(line, from),
+                    Boundary::Line if from == 0 => query.find_line(line),
+                    Boundary::Line => None,
+                };
+                found.map(|m| (0, m.span, m.distance))
+            },
+        }
+    }
+}
+
+// the byte after the char starting at index, or one past the end at the end of the line
+fn next_char(line: &str, index: usize) -> usize {
+    index + line[index..].chars().next().map_or(1, |c| c.len_utf8())
+}
+
+// the same word chars as \w in a regex: letters and digits in any script, plus combining marks
+// (so the accent in a decomposed é doesn't split a word) and connector punctuation like _
+pub fn is_word_char(c: char) -> bool {
+    if c.is_alphanumeric() {
+        return true;
+    }
+
+    match c {
+        // connector punctuation
+        '_' | '\u{203f}' | '\u{2040}' | '\u{2054}' | '\u{fe33}' | '\u{fe34}' | '\u{fe4d}'..='\u{fe4f}' | '\u{ff3f}' => true,
+        // zero width non-joiner and joiner, which sit inside words in scripts like Persian
+        '\u{200c}' | '\u{200d}' => true,
+        // the combining mark blocks
+        '\u{300}'..='\u{36f}' | '\u{1ab0}'..='\u{1aff}' | '\u{1dc0}'..='\u{1dff}' | '\u{20d0}'..='\u{20ff}' |
+        '\u{fe20}'..='\u{fe2f}' => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,23 @@
+use std::fs::File;
+
+use memmap2::Mmap;
+
+// files smaller than this are read, setting up a map (and the page faults as it's first touched)
+// costs more than copying a few pages, see benches/mmap.rs
+const MIN_MAP_LEN: u64 = 64 * 1024;
+
+// maps a file into memory so it can be searched as one byte slice, without reading a copy of it
+// onto the heap; None when that isn't possible or isn't worth it, e.g. for pipes, devices and
+// small files, which are read a line at a time instead
+//
+// a map is unsafe because the file can change underneath it: another process truncating the file
+// mid search gets minigrep killed with SIGBUS, which is why --no-mmap is there
+pub fn map(file: &File) -> Option<Mmap> {
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() < MIN_MAP_LEN {
+        return None;
+    }
+
+    // if mapping fails (some filesystems can't), reading the file still works
+    unsafe { Mmap::map(file).ok() }
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+use fold::{fold, Fold};
+
+// an Aho-Corasick automaton: a trie of every pattern where each node also has a failure link to
+// the longest suffix of it that's also in the trie, so one pass over a line finds every pattern
+// in it rather than looping over the patterns calling contains for each
+//
+// the trie is built over chars, case folded when matching case insensitively, so it agrees with
+// FoldedQuery on things like ß matching SS
+#[derive(Debug)]
+pub struct MultiPattern {
+    nodes: Vec<Node>,
+    // how many (folded) chars long each pattern is, to work out where a match started
+    lengths: Vec<usize>,
+    // the first empty pattern, if any, which matches every line
+    empty: Option<usize>,
+    case_sensitive: bool,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    next: HashMap<char, usize>,
+    fail: usize,
+    // how many chars into a pattern this node is
+    depth: usize,
+    // the patterns ending at this node, including those ending at nodes down its failure links
+    outputs: Vec<usize>,
+}
+
+// a match of one of the patterns, by its index in the list given to MultiPattern::new
+#[derive(Debug, PartialEq)]
+pub struct PatternMatch {
+    pub pattern: usize,
+    pub span: Range<usize>,
+}
+
+impl MultiPattern {
+    pub fn new(patterns: &[String], case_sensitive: bool) -> MultiPattern {
+        let mut multi = MultiPattern {
+            nodes: vec![Node::default()],
+            lengths: Vec::new(),
+            empty: None,
+            case_sensitive,
+        };
+
+        for (index, pattern) in patterns.iter().enumerate() {
+            let chars: Vec<char> = if case_sensitive {
+                pattern.chars().collect()
+            } else {
+                pattern.chars().flat_map(fold).collect()
+            };
+            multi.lengths.push(chars.len());
+
+            if chars.is_empty() {
+                multi.empty = multi.empty.or(Some(index));
+                continue;
+            }
+
+            let mut node = 0;
+            for c in chars {
+                node = match multi.nodes[node].next.get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        let depth = multi.nodes[node].depth + 1;
+                        multi.nodes.push(Node { depth, ..Node::default() });
+                        let next = multi.nodes.len() - 1;
+                        multi.nodes[node].next.insert(c, next);
+                        next
+                    },
+                };
+            }
+            multi.nodes[node].outputs.push(index);
+        }
+
+        multi.link_failures();
+        multi
+    }
+
+    // a breadth first walk of the trie, so a node's failure link is always worked out before its
+    // children need it
+    fn link_failures(&mut self) {
+        let mut queue: VecDeque<usize> = self.nodes[0].next.values().cloned().collect();
+
+        while let Some(node) = queue.pop_front() {
+            let children: Vec<(char, usize)> = self.nodes[node].next.iter().map(|(&c, &n)| (c, n)).collect();
+
+            for (c, child) in children {
+                // follow failure links up from the parent until one can carry on with c
+                let mut fail = self.nodes[node].fail;
+                let target = loop {
+                    if let Some(&next) = self.nodes[fail].next.get(&c) {
+                        break next;
+                    }
+                    if fail == 0 {
+                        break 0;
+                    }
+                    fail = self.nodes[fail].fail;
+                };
+
+                self.nodes[child].fail = target;
+                let inherited = self.nodes[target].outputs.clone();
+                self.nodes[child].outputs.extend(inherited);
+                queue.push_back(child);
+            }
+        }
+    }
+
+    fn step(&self, mut node: usize, c: char) -> usize {
+        loop {
+            if let Some(&next) = self.nodes[node].next.get(&c) {
+                return next;
+            }
+            if node == 0 {
+                return 0;
+            }
+            node = self.nodes[node].fail;
+        }
+    }
+
+    // runs the automaton over the line from byte from, calling visit after each char of the line
+    // with the matches that ended there and where the longest match still in progress started (if
+    // one is), so a search can stop once nothing better can turn up; visit returns false to stop
+    fn scan<F>(&self, line: &str, from: usize, mut visit: F)
+        where F: FnMut(&mut Vec<PatternMatch>, Option<usize>) -> bool {
+        // for each (folded) char seen, the byte its line char starts at and whether it's the first
+        // char of that char's folding, so a match can only start on a whole char of the line
+        let mut starts: Vec<(usize, bool)> = Vec::new();
+        let mut ended = Vec::new();
+        let mut node = 0;
+
+        for (index, c) in line[from..].char_indices() {
+            let index = from + index;
+            let folded = if self.case_sensitive { Fold::unchanged(c) } else { fold(c) };
+
+            for (position, folded_char) in folded.enumerate() {
+                starts.push((index, position == 0));
+                node = self.step(node, folded_char);
+            }
+
+            // a match can also only end once the whole line char has been used up
+            for &pattern in &self.nodes[node].outputs {
+                let (start, whole) = starts[starts.len() - self.lengths[pattern]];
+                if whole {
+                    ended.push(PatternMatch { pattern, span: start..index + c.len_utf8() });
+                }
+            }
+            let depth = self.nodes[node].depth;
+            let in_progress = if depth > 0 { Some(starts[starts.len() - depth].0) } else { None };
+            if !visit(&mut ended, in_progress) {
+                return;
+            }
+            ended.clear();
+        }
+    }
+
+    // the leftmost match in the line (the longest, if several start at the same place)
+    pub fn find(&self, line: &str) -> Option<PatternMatch> {
+        self.find_from(line, 0)
+    }
+
+    // like find, but only for matches starting at or after byte from; the automaton starts there,
+    // and stops as soon as no match still in progress could start at or before the best one found
+    pub fn find_from(&self, line: &str, from: usize) -> Option<PatternMatch> {
+        let mut found: Option<PatternMatch> = None;
+        self.scan(line, from, |ended, in_progress| {
+            for m in ended.drain(..) {
+                if found.as_ref().is_none_or(|best| leftmost_longest(&m) < leftmost_longest(best)) {
+                    found = Some(m);
+                }
+            }
+            match found {
+                Some(ref best) => in_progress.is_some_and(|start| start <= best.span.start),
+                None => true,
+            }
+        });
+
+        match (found, self.empty) {
+            (Some(found), _) => Some(found),
+            (None, Some(pattern)) => Some(PatternMatch { pattern, span: from..from }),
+            (None, None) => None,
+        }
+    }
+}
+
+// a key that orders matches leftmost first, then longest first
+fn leftmost_longest(m: &PatternMatch) -> (usize, usize) {
+    (m.span.start, usize::MAX - m.span.end)
+}
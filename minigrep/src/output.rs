@@ -0,0 +1,326 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+
+use {ColorChoice, Config, OutputMode, Record, Sink};
+
+// ANSI escape codes, using the same colors as GNU grep (--interactive uses them too)
+pub(crate) const MATCH_COLOR: &str = "\x1b[1;31m";
+pub(crate) const NAME_COLOR: &str = "\x1b[35m";
+pub(crate) const NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const PATTERN_COLOR: &str = "\x1b[33m";
+pub(crate) const RESET: &str = "\x1b[0m";
+
+// prints matches for the CLI, optionally with filename, line number and byte offset prefixes and
+// lines of context around each match (separated by -- like GNU grep); it's the Sink a Searcher
+// hands each line to, working out the context is the searcher's job
+//
+// in the other output modes lines are ignored and finish prints a count or filename instead,
+// apart from --json which prints every line as a JSON object (see json_line)
+//
+// output goes to any Write, stdout normally or a Vec<u8> when searching files in parallel
+pub struct Printer<W: Write> {
+    out: W,
+    mode: OutputMode,
+    color: bool,
+    prefix: bool,
+    line_number: bool,
+    byte_offset: bool,
+    // print a label (the pattern, or the --fuzzy distance) before each matching line
+    show_label: bool,
+    patterns: Vec<String>,
+    // whether there's any context, so groups of lines need separating
+    context: bool,
+    // the source currently being printed, and whether it's binary, so only says if it matched
+    name: String,
+    binary: bool,
+    last_printed: Option<usize>,
+    // set once a group of context lines has been printed, so the next group gets a separator
+    printed_group: bool,
+    // totals over every source, for the --json summary
+    files: usize,
+    files_with_matches: usize,
+    matches: usize,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(config: &Config, prefix: bool, out: W) -> Printer<W> {
+        Printer {
+            out,
+            mode: config.mode,
+            color: use_color(config.color),
+            prefix,
+            line_number: config.line_number,
+            byte_offset: config.byte_offset,
+            show_label: config.show_pattern || config.fuzzy.is_some(),
+            patterns: config.patterns.clone(),
+            context: config.before_context > 0 || config.after_context > 0,
+            name: String::new(),
+            binary: false,
+            last_printed: None,
+            printed_group: false,
+            files: 0,
+            files_with_matches: 0,
+            matches: 0,
+        }
+    }
+
+    // called before the lines of each new source
+    pub fn start(&mut self, name: &str, binary: bool) -> io::Result<()> {
+        self.name = name.to_string();
+        self.binary = binary;
+        self.last_printed = None;
+
+        if self.mode == OutputMode::Json {
+            writeln!(self.out, "{{\"type\":\"begin\",\"file\":{}}}", json_string(name))?;
+        }
+        Ok(())
+    }
+
+    // called after the lines of each source, with how many lines were selected
+    pub fn finish(&mut self, count: usize) -> io::Result<()> {
+        let name = self.paint(NAME_COLOR, &self.name);
+        self.count(count);
+
+        match self.mode {
+            OutputMode::Count if self.prefix => {
+                writeln!(self.out, "{}{}{}", name, self.paint(SEPARATOR_COLOR, ":"), count)
+            },
+            OutputMode::Count => writeln!(self.out, "{}", count),
+            OutputMode::FilesWithMatches if count > 0 => writeln!(self.out, "{}", name),
+            OutputMode::FilesWithoutMatch if count == 0 => writeln!(self.out, "{}", name),
+            OutputMode::Json => {
+                writeln!(self.out, "{{\"type\":\"end\",\"file\":{},\"matches\":{}}}", json_string(&self.name), count)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // called once every source has been searched, only --json prints anything
+    pub fn summary(&mut self) -> io::Result<()> {
+        if self.mode != OutputMode::Json {
+            return Ok(());
+        }
+        writeln!(self.out, "{{\"type\":\"summary\",\"files\":{},\"files_with_matches\":{},\"matches\":{}}}",
+                 self.files, self.files_with_matches, self.matches)
+    }
+
+    fn count(&mut self, count: usize) {
+        self.files += 1;
+        self.matches += count;
+        if count > 0 {
+            self.files_with_matches += 1;
+        }
+    }
+
+    // writes everything another printer produced for a source (count being how many lines it
+    // selected, or None if it failed part way and was never finished), adding the -- separator
+    // between groups of context that printing the sources one after another would have
+    pub fn append(&mut self, output: &[u8], count: Option<usize>) -> io::Result<()> {
+        if let Some(count) = count {
+            self.count(count);
+        }
+        if output.is_empty() {
+            return Ok(());
+        }
+        if self.printed_group && self.mode == OutputMode::Lines && self.context {
+            writeln!(self.out, "{}", self.paint(SEPARATOR_COLOR, "--"))?;
+        }
+        self.printed_group = true;
+
+        self.out.write_all(output)
+    }
+
+    // gives back the writer, e.g. to get at the bytes written to a Vec<u8>
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // matching lines use : between prefixes and context lines use -, as grep does
+    fn print_line(&mut self, line_number: usize, byte_offset: usize, separator: char, line: &str,
+                  label: Option<&str>, spans: &[Range<usize>]) -> io::Result<()> {
+        // the other modes only print something once the source is finished, and a binary file's
+        // lines would only be garbage
+        if self.mode != OutputMode::Lines || self.binary {
+            return Ok(());
+        }
+
+        if self.context {
+            // a gap since the last line printed (or a new source) starts a new group
+            let new_group = match self.last_printed {
+                Some(last) => last + 1 != line_number,
+                None => true,
+            };
+            if new_group && self.printed_group {
+                writeln!(self.out, "{}", self.paint(SEPARATOR_COLOR, "--"))?;
+            }
+            self.printed_group = true;
+            self.last_printed = Some(line_number);
+        }
+
+        let mut line = self.highlight(line, spans);
+        if self.show_label && separator == ':' {
+            let label = self.paint(PATTERN_COLOR, label.unwrap_or(""));
+            line = format!("{}{}{}", label, self.paint(SEPARATOR_COLOR, ":"), line);
+        }
+
+        let mut out = String::new();
+        let separator = self.paint(SEPARATOR_COLOR, &separator.to_string());
+
+        if self.prefix {
+            out.push_str(&format!("{}{}", self.paint(NAME_COLOR, &self.name), separator));
+        }
+        if self.line_number {
+            out.push_str(&format!("{}{}", self.paint(NUMBER_COLOR, &line_number.to_string()), separator));
+        }
+        if self.byte_offset {
+            out.push_str(&format!("{}{}", self.paint(NUMBER_COLOR, &byte_offset.to_string()), separator));
+        }
+
+        writeln!(self.out, "{}{}", out, line)
+    }
+
+    // one JSON object on a line of its own, like
+    // {"type":"match","file":"poem.txt","line_number":2,"byte_offset":6,
+    //  "submatches":[{"start":15,"end":19,"text":"duct"}],"line":"safe, fast, productive."}
+    // where byte_offset is where the line starts and submatches are byte ranges within the line;
+    // a -U match covering several lines also has an end_line_number, and newlines in its line,
+    // and a --fuzzy match has the distance of its closest match
+    fn json_line(&mut self, kind: &str, record: &Record) -> io::Result<()> {
+        let submatches: Vec<String> = record.spans.iter()
+            .map(|span| {
+                format!("{{\"start\":{},\"end\":{},\"text\":{}}}", span.start, span.end,
+                        json_string(&record.line[span.clone()]))
+            })
+            .collect();
+        let end_line_number = if record.end_line_number > record.line_number {
+            format!(",\"end_line_number\":{}", record.end_line_number)
+        } else {
+            String::new()
+        };
+        let distance = match record.distance {
+            Some(distance) => format!(",\"distance\":{}", distance),
+            None => String::new(),
+        };
+
+        writeln!(self.out, "{{\"type\":\"{}\",\"file\":{},\"line_number\":{}{},\"byte_offset\":{},\"submatches\":[{}]{},\"line\":{}}}",
+                 kind, json_string(&self.name), record.line_number, end_line_number, record.byte_offset,
+                 submatches.join(","), distance, json_string(&record.line))
+    }
+
+    // wraps each span of the line in the match color
+    fn highlight(&self, line: &str, spans: &[Range<usize>]) -> String {
+        let mut out = String::new();
+        let mut last = 0;
+
+        for span in spans {
+            out.push_str(&line[last..span.start]);
+            out.push_str(&self.paint(MATCH_COLOR, &line[span.clone()]));
+            last = span.end;
+        }
+        out.push_str(&line[last..]);
+
+        out
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            paint(color, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// text in color, then back to the terminal's own
+pub(crate) fn paint(color: &str, text: &str) -> String {
+    format!("{}{}{}", color, text, RESET)
+}
+
+impl<W: Write> Sink for Printer<W> {
+    fn matched(&mut self, record: &Record) -> io::Result<bool> {
+        // like grep, a binary file says it matched and stops there
+        if self.binary && self.mode == OutputMode::Lines {
+            writeln!(self.out, "Binary file {} matches", self.name)?;
+            return Ok(false);
+        }
+        if self.mode == OutputMode::Json {
+            self.json_line("match", record)?;
+            return Ok(true);
+        }
+        // the pattern (or with --fuzzy, how many edits away the match is) is only known for lines
+        // that matched, not inverted ones
+        let label = match (record.distance, record.pattern) {
+            (Some(distance), _) => Some(format!("~{}", distance)),
+            (None, Some(pattern)) if self.show_label => self.patterns.get(pattern).cloned(),
+            _ => None,
+        };
+        if record.end_line_number == record.line_number {
+            self.print_line(record.line_number, record.byte_offset, ':', &record.line, label.as_deref(), &record.spans)?;
+            return Ok(true);
+        }
+
+        // a -U match covering several lines prints each of them, with its own line number, and
+        // the parts of the matches on that line
+        let mut start = 0;
+        for (index, line) in record.line.split('\n').enumerate() {
+            let text = line.strip_suffix('\r').unwrap_or(line);
+            let end = start + text.len();
+            let spans: Vec<Range<usize>> = record.spans.iter()
+                .filter(|span| span.start < end && span.end > start)
+                .map(|span| span.start.max(start) - start..span.end.min(end) - start)
+                .collect();
+
+            self.print_line(record.line_number + index, record.byte_offset + start, ':', text, label.as_deref(), &spans)?;
+            start += line.len() + 1;
+        }
+        Ok(true)
+    }
+
+    fn context(&mut self, record: &Record) -> io::Result<bool> {
+        if self.mode == OutputMode::Json {
+            self.json_line("context", record)?;
+        } else {
+            self.print_line(record.line_number, record.byte_offset, '-', &record.line, None, &[])?;
+        }
+        Ok(true)
+    }
+
+    // only highlighting and JSON need every match in a line
+    fn wants_all_spans(&self) -> bool {
+        self.color || self.mode == OutputMode::Json
+    }
+}
+
+// auto only colors when stdout is a terminal, and anyone setting NO_COLOR (see no-color.org) has
+// asked not to have color unless they pass --color=always
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        },
+    }
+}
+
+// a string as a quoted JSON string, escaping quotes, backslashes and control chars
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use output::Printer;
+use {report, search_source, Config, Incomplete, OutputMode, Searcher, Source};
+
+// searches sources on a pool of config.threads worker threads, much like the ThreadPool in the
+// book: jobs go down a channel the workers share behind a Mutex, and results come back on another
+//
+// each worker prints a whole source into its own buffer, and buffers are written out in the order
+// the sources were given, so the output is the same as searching them one at a time
+pub fn run(config: Config, searcher: Searcher, prefix: bool, sources: Vec<Source>)
+    -> Result<bool, Box<dyn Error>> {
+    // Arc lets every thread share the config and searcher without copying them
+    let config = Arc::new(config);
+    let searcher = Arc::new(searcher);
+
+    let (job_sender, job_receiver) = mpsc::channel::<(usize, Source)>();
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+    let (result_sender, result_receiver) = mpsc::channel();
+
+    for _ in 0..config.threads {
+        let job_receiver = Arc::clone(&job_receiver);
+        let result_sender = result_sender.clone();
+        let config = Arc::clone(&config);
+        let searcher = Arc::clone(&searcher);
+
+        thread::spawn(move || loop {
+            // the lock is only held while taking the next job, so workers search at the same time
+            let job = job_receiver.lock().unwrap().recv();
+            let (index, source) = match job {
+                Ok(job) => job,
+                // the sender has gone and the queue is empty, so there's no more work
+                Err(_) => break,
+            };
+
+            let mut printer = Printer::new(&config, prefix, Vec::new());
+            // what was printed before a read error is kept, as it would have been written out
+            let result = search_source(&config, &searcher, &mut printer, &source).map(|searched| {
+                (searched.map_err(|e| (source.name(), e)), printer.into_inner())
+            });
+
+            // the receiver goes away if run returns early, e.g. on an error or with -q
+            if result_sender.send((index, result)).is_err() {
+                break;
+            }
+        });
+    }
+
+    for job in sources.into_iter().enumerate() {
+        job_sender.send(job)?;
+    }
+    // dropping our senders lets the workers, and then the loop below, know when they're done
+    drop(job_sender);
+    drop(result_sender);
+
+    let stdout = io::stdout();
+    let mut printer = Printer::new(&config, prefix, stdout.lock());
+    let mut selected = false;
+    let mut failed = false;
+
+    // results arrive in whatever order the workers finish, so hold on to them until it's their turn
+    let mut pending = HashMap::new();
+    let mut next = 0;
+
+    for (index, result) in result_receiver {
+        pending.insert(index, result);
+
+        while let Some(result) = pending.remove(&next) {
+            // sources that couldn't be opened or read are reported in their turn too
+            match result? {
+                (Ok(count), output) => {
+                    printer.append(&output, Some(count))?;
+                    selected |= count > 0;
+                },
+                (Err((name, e)), output) => {
+                    printer.append(&output, None)?;
+                    report(&name, &e);
+                    failed = true;
+                },
+            }
+            next += 1;
+
+            if selected && config.mode == OutputMode::Quiet {
+                return Ok(true);
+            }
+        }
+    }
+    printer.summary()?;
+
+    if failed {
+        return Err(Incomplete.into());
+    }
+    Ok(selected)
+}
@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use {report, walk, Config, Incomplete, Matcher, Source};
+
+// a line that --replace changes, keeping its line ending so the file can be put back together
+struct Change {
+    line_number: usize,
+    old: String,
+    new: String,
+}
+
+// rewrites every source with each match replaced, or with --dry-run prints a unified diff of what
+// would change instead, returning whether any line matched
+//
+// every match in a file is replaced (Config won't take -v or -m along with --in-place); a file
+// that can't be read or written is reported and the rest are still rewritten, while walked files
+// that are binary or aren't UTF-8 are skipped, as they would be by a search
+pub fn run(config: &Config, matcher: &Matcher, sources: &[Source]) -> Result<bool, Box<dyn Error>> {
+    let replacement = config.replace.as_ref().map_or("", |replacement| replacement.as_str());
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut selected = false;
+    let mut failed = false;
+
+    for source in sources {
+        let (path, walked) = match *source {
+            Source::Stdin => return Err("--in-place can't rewrite standard input".into()),
+            Source::File(ref path) => (path, false),
+            // the index says there's nothing to replace
+            Source::Skipped(_) => continue,
+            Source::Walked(ref path) => (path, true),
+        };
+
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                report(&source.name(), &e);
+                failed = true;
+                continue;
+            },
+        };
+        if walked && walk::is_binary(&bytes) {
+            continue;
+        }
+        let contents = match String::from_utf8(bytes) {
+            Ok(contents) => contents,
+            Err(_) if walked => continue,
+            Err(e) => {
+                report(&source.name(), &e);
+                failed = true;
+                continue;
+            },
+        };
+        let (replaced, changes, matched) = replace_lines(matcher, &contents, replacement);
+        selected |= matched;
+
+        if changes.is_empty() {
+            continue;
+        }
+        if config.dry_run {
+            print_diff(&mut out, &source.name(), &changes)?;
+        } else if let Err(e) = write_atomically(path, &replaced) {
+            report(&source.name(), &e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        return Err(Incomplete.into());
+    }
+    Ok(selected)
+}
+
+// the contents with every match replaced, the lines that changed, and whether any line matched
+fn replace_lines(matcher: &Matcher, contents: &str, replacement: &str) -> (String, Vec<Change>, bool) {
+    let mut replaced = String::new();
+    let mut changes = Vec::new();
+    let mut matched = false;
+
+    for (index, line) in contents.split_inclusive('\n').enumerate() {
+        // split the line ending off, so a match can't cover it and it's kept as it was
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let ending = &line[text.len()..];
+
+        if matcher.find(text).is_none() {
+            replaced.push_str(line);
+            continue;
+        }
+        matched = true;
+
+        let (new, _) = matcher.replace(text, replacement);
+        let new = format!("{}{}", new, ending);
+        replaced.push_str(&new);
+        if new != line {
+            changes.push(Change { line_number: index + 1, old: line.to_string(), new });
+        }
+    }
+
+    (replaced, changes, matched)
+}
+
+// a unified diff without context lines, so each run of changed lines is a hunk; patch -p0 can
+// apply it
+fn print_diff<W: Write>(out: &mut W, name: &str, changes: &[Change]) -> io::Result<()> {
+    writeln!(out, "--- {}", name)?;
+    writeln!(out, "+++ {}", name)?;
+
+    // a replacement with a newline in it adds lines, moving where later hunks start in the new file
+    let mut added = 0isize;
+    let mut start = 0;
+
+    while start < changes.len() {
+        // the hunk runs until the next line that isn't straight after the one before
+        let mut end = start + 1;
+        while end < changes.len() && changes[end].line_number == changes[end - 1].line_number + 1 {
+            end += 1;
+        }
+        let hunk = &changes[start..end];
+
+        let old_lines = hunk.len();
+        let new_lines: usize = hunk.iter().map(|change| change.new.split_inclusive('\n').count()).sum();
+        let old_start = hunk[0].line_number;
+        let new_start = (old_start as isize + added) as usize;
+        writeln!(out, "@@ -{},{} +{},{} @@", old_start, old_lines, new_start, new_lines)?;
+
+        for change in hunk {
+            print_lines(out, '-', &change.old)?;
+        }
+        for change in hunk {
+            print_lines(out, '+', &change.new)?;
+        }
+
+        added += new_lines as isize - old_lines as isize;
+        start = end;
+    }
+
+    Ok(())
+}
+
+// each line of text prefixed with - or +, marking a last line without a newline like diff does
+fn print_lines<W: Write>(out: &mut W, prefix: char, text: &str) -> io::Result<()> {
+    for line in text.split_inclusive('\n') {
+        write!(out, "{}{}", prefix, line)?;
+        if !line.ends_with('\n') {
+            writeln!(out)?;
+            writeln!(out, "\\ No newline at end of file")?;
+        }
+    }
+
+    Ok(())
+}
+
+// writes the new contents to a temp file next to the original and renames it over the top, so
+// anything reading the file sees either all of the old contents or all of the new, never half
+// written; the temp file has to be in the same directory, as rename can't move across filesystems
+//
+// the temp file's name is easy to guess, so it's only ever created new: anything already there
+// (like a symlink someone put there to get another file overwritten) is an error, not written to
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let temp = dir.join(format!(".{}.minigrep-{}", name, process::id()));
+
+    // opened first, so a file that was already there is never cleaned up as if it were ours
+    let mut file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+
+    // a closure, so ? can be used and the temp file still cleaned up on any error
+    let mut write = || -> io::Result<()> {
+        file.write_all(contents.as_bytes())?;
+        // make sure the contents are on disk before the rename makes them the file's
+        file.sync_all()?;
+        fs::set_permissions(&temp, fs::metadata(path)?.permissions())?;
+        fs::rename(&temp, path)
+    };
+
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
@@ -0,0 +1,622 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
+
+use memchr::memchr;
+
+use matcher::{Boundary, Case, Matcher};
+use {line_text, read_line, Config, OutputMode};
+
+// sets up a Searcher, for using minigrep as a library; it starts out as a case sensitive search
+// for literal patterns, with no context and no limit, and each method changes one thing:
+//
+//     let searcher = SearcherBuilder::new()
+//         .pattern("duct")
+//         .case(Case::Insensitive)
+//         .after_context(1)
+//         .build()?;
+//
+// like the ignore crate's WalkBuilder, the methods take &mut self, so a builder can be kept
+// around and changed between searches
+#[derive(Debug, Clone)]
+pub struct SearcherBuilder {
+    patterns: Vec<String>,
+    regex: bool,
+    case: Case,
+    boundary: Boundary,
+    fuzzy: Option<usize>,
+    multiline: bool,
+    invert: bool,
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<usize>,
+    replace: Option<String>,
+}
+
+impl Default for SearcherBuilder {
+    fn default() -> SearcherBuilder {
+        SearcherBuilder::new()
+    }
+}
+
+impl SearcherBuilder {
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder {
+            patterns: Vec::new(),
+            regex: false,
+            case: Case::Sensitive,
+            boundary: Boundary::Anywhere,
+            fuzzy: None,
+            multiline: false,
+            invert: false,
+            before_context: 0,
+            after_context: 0,
+            max_count: None,
+            replace: None,
+        }
+    }
+
+    // the builder for the command line's options; -l, -L and -q only care whether anything is
+    // selected, so they stop at the first line, and only printing lines needs context
+    pub fn from_config(config: &Config) -> SearcherBuilder {
+        let case = match (config.smart_case, config.case_sensitive) {
+            (true, _) => Case::Smart,
+            (false, true) => Case::Sensitive,
+            (false, false) => Case::Insensitive,
+        };
+        let max_count = match config.mode {
+            OutputMode::Lines | OutputMode::Count | OutputMode::Json => config.max_count,
+            _ => Some(1),
+        };
+        let (before_context, after_context) = match config.mode {
+            OutputMode::Lines | OutputMode::Json => (config.before_context, config.after_context),
+            _ => (0, 0),
+        };
+
+        SearcherBuilder {
+            patterns: config.patterns.clone(),
+            regex: config.regex,
+            case,
+            boundary: config.boundary,
+            fuzzy: config.fuzzy,
+            multiline: config.multiline,
+            invert: config.invert,
+            before_context,
+            after_context,
+            max_count,
+            replace: config.replace.clone(),
+        }
+    }
+
+    // adds a pattern to search for, a line matches if any of them do (with no patterns at all,
+    // nothing matches)
+    pub fn pattern(&mut self, pattern: &str) -> &mut SearcherBuilder {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    // treat the patterns as regular expressions rather than literal text
+    pub fn regex(&mut self, regex: bool) -> &mut SearcherBuilder {
+        self.regex = regex;
+        self
+    }
+
+    pub fn case(&mut self, case: Case) -> &mut SearcherBuilder {
+        self.case = case;
+        self
+    }
+
+    // whether matches have to be whole words or lines
+    pub fn boundary(&mut self, boundary: Boundary) -> &mut SearcherBuilder {
+        self.boundary = boundary;
+        self
+    }
+
+    // match text up to this many edits from the first pattern (which can't be a regex)
+    pub fn fuzzy(&mut self, max_distance: Option<usize>) -> &mut SearcherBuilder {
+        self.fuzzy = max_distance;
+        self
+    }
+
+    // search the whole input at once rather than a line at a time, so a match can span lines; the
+    // lines a match covers make up one record, see Matcher::from_options for what changes in a regex
+    pub fn multiline(&mut self, multiline: bool) -> &mut SearcherBuilder {
+        self.multiline = multiline;
+        self
+    }
+
+    // select the lines that don't match instead
+    pub fn invert(&mut self, invert: bool) -> &mut SearcherBuilder {
+        self.invert = invert;
+        self
+    }
+
+    // how many lines of context to give before and after each selected line
+    pub fn before_context(&mut self, lines: usize) -> &mut SearcherBuilder {
+        self.before_context = lines;
+        self
+    }
+
+    pub fn after_context(&mut self, lines: usize) -> &mut SearcherBuilder {
+        self.after_context = lines;
+        self
+    }
+
+    pub fn context(&mut self, lines: usize) -> &mut SearcherBuilder {
+        self.before_context(lines).after_context(lines)
+    }
+
+    // stop reading after this many selected lines
+    pub fn max_count(&mut self, max_count: Option<usize>) -> &mut SearcherBuilder {
+        self.max_count = max_count;
+        self
+    }
+
+    // give each selected line with its matches replaced (see Matcher::replace)
+    pub fn replace(&mut self, replacement: Option<&str>) -> &mut SearcherBuilder {
+        self.replace = replacement.map(|replacement| replacement.to_string());
+        self
+    }
+
+    // compiles the patterns, failing if one is an invalid regex or a fuzzy query is too long
+    pub fn build(&self) -> Result<Searcher, String> {
+        if self.fuzzy.is_some() && (self.regex || self.patterns.len() > 1 || self.multiline) {
+            return Err("fuzzy matching only works with a single literal pattern, a line at a time".to_string());
+        }
+        let matcher = Matcher::from_options(&self.patterns, self.case, self.regex, self.boundary, self.fuzzy,
+                                            self.multiline)?;
+
+        Ok(Searcher {
+            matcher,
+            multiline: self.multiline,
+            invert: self.invert,
+            before_context: self.before_context,
+            after_context: self.after_context,
+            max_count: self.max_count,
+            replace: self.replace.clone(),
+        })
+    }
+}
+
+// searches any Read for selected lines, handing them back one Record at a time (see search) or
+// passing them to a Sink (see search_sink); a Searcher can be shared between threads, each search
+// keeps its own state
+#[derive(Debug)]
+pub struct Searcher {
+    matcher: Matcher,
+    multiline: bool,
+    invert: bool,
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<usize>,
+    replace: Option<String>,
+}
+
+// whether a record is a selected line or one around it
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RecordKind {
+    Match,
+    Context,
+}
+
+// a line a search found, either selected or context
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    pub kind: RecordKind,
+    // line numbers start at 1
+    pub line_number: usize,
+    // the last line the record covers, only ever after line_number with multiline matching, when
+    // line has newlines in it
+    pub end_line_number: usize,
+    // where the line starts in the input
+    pub byte_offset: usize,
+    // the line without its line ending, lossily converted to UTF-8, and with any replacement made
+    pub line: String,
+    // the byte ranges of every match in line (or of every replacement), empty for context and
+    // inverted lines
+    pub spans: Vec<Range<usize>>,
+    // the index of the pattern that matched first, None for context and inverted lines
+    pub pattern: Option<usize>,
+    // with fuzzy matching, how many edits the closest match is from the query
+    pub distance: Option<usize>,
+}
+
+// somewhere for search_sink to send records, instead of printing them
+pub trait Sink {
+    // called for each selected line, returning false stops the search
+    fn matched(&mut self, record: &Record) -> io::Result<bool>;
+
+    // called for each line of context, which there only is with before or after context set
+    fn context(&mut self, _record: &Record) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    // finding every match in a line is extra work, a sink that doesn't need them can say so and
+    // get only the first in spans
+    fn wants_all_spans(&self) -> bool {
+        true
+    }
+}
+
+impl Searcher {
+    // the matcher the patterns were compiled into
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    // an iterator over the records for reader, reading a line at a time so memory use stays the
+    // same however big the input is; after an error, the iterator ends
+    pub fn search<R: Read>(&self, reader: R) -> Records<'_, BufReader<R>> {
+        self.search_buffered(BufReader::new(reader))
+    }
+
+    // like search, for a reader that's already buffered
+    pub fn search_buffered<R: BufRead>(&self, reader: R) -> Records<'_, R> {
+        Records { reader, buf: Vec::new(), selection: Selection::new(self, true), done: false }
+    }
+
+    // searches reader, passing each record to sink, returning how many lines were selected
+    pub fn search_sink<R: Read, S: Sink>(&self, reader: R, sink: &mut S) -> io::Result<usize> {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        let mut selection = Selection::new(self, sink.wants_all_spans());
+
+        if self.multiline {
+            reader.read_to_end(&mut buf)?;
+            self.search_multiline(&buf, &mut selection, |selection| selection.send(sink))?;
+            return Ok(selection.count);
+        }
+
+        while selection.wants_more() {
+            let (read, line) = match read_line(&mut reader, &mut buf)? {
+                Some(line) => line,
+                None => break,
+            };
+            selection.line(&line, read);
+            if !selection.send(sink)? {
+                break;
+            }
+        }
+
+        Ok(selection.count)
+    }
+
+    // like search_sink, for input that's all in memory already (like a memory mapped file), which
+    // is searched where it is rather than copied a line at a time into a buffer
+    pub fn search_slice<S: Sink>(&self, bytes: &[u8], sink: &mut S) -> io::Result<usize> {
+        let mut rest = bytes;
+        let mut selection = Selection::new(self, sink.wants_all_spans());
+
+        if self.multiline {
+            self.search_multiline(bytes, &mut selection, |selection| selection.send(sink))?;
+            return Ok(selection.count);
+        }
+
+        while selection.wants_more() && !rest.is_empty() {
+            let read = memchr(b'\n', rest).map_or(rest.len(), |newline| newline + 1);
+            let (line, next) = rest.split_at(read);
+            rest = next;
+
+            selection.line(&line_text(line), read);
+            if !selection.send(sink)? {
+                break;
+            }
+        }
+
+        Ok(selection.count)
+    }
+
+    // with multiline matching, every match in the whole of bytes is found first, and the lines
+    // each one covers (along with any other match on them) are selected together as one record,
+    // going through the rest a line at a time like the other searches; after is called after
+    // each line or group of lines, and stops the search by returning false
+    fn search_multiline<F>(&self, bytes: &[u8], selection: &mut Selection, mut after: F) -> io::Result<()>
+        where F: FnMut(&mut Selection) -> io::Result<bool> {
+        // a newline is always a newline after a lossy conversion, so text has the same lines as
+        // bytes, they're only a different length when some of it isn't UTF-8
+        let text = String::from_utf8_lossy(bytes);
+        let lines = line_ranges(text.as_bytes());
+        let lengths: Vec<usize> = line_ranges(bytes).iter().map(|range| range.len()).collect();
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        // which line a byte of text is on
+        let line_of = |offset: usize| lines.partition_point(|range| range.start <= offset) - 1;
+
+        let mut groups: Vec<Group> = Vec::new();
+        for (pattern, span) in self.matcher.find_patterns(&text) {
+            // there's no line after a last newline, for an empty match there (like ^$) to select
+            if span.is_empty() && span.start == text.len() && text.ends_with('\n') {
+                continue;
+            }
+            // a match ending with a newline ends on the line the newline is on
+            let first = line_of(span.start);
+            let last = if span.is_empty() { first } else { line_of(span.end - 1) };
+
+            match groups.last_mut() {
+                Some(group) if first < group.lines.end => {
+                    group.lines.end = group.lines.end.max(last + 1);
+                    group.spans.push(span);
+                },
+                _ => groups.push(Group { lines: first..last + 1, pattern, spans: vec![span] }),
+            }
+        }
+
+        let mut groups = groups.into_iter().peekable();
+        let mut index = 0;
+        while index < lines.len() && selection.wants_more() {
+            // the group of matched lines index is on, if it's in one
+            while groups.peek().is_some_and(|group| group.lines.end <= index) {
+                groups.next();
+            }
+            let group = groups.peek().filter(|group| group.lines.start <= index);
+
+            match group {
+                Some(group) if group.lines.start == index && !self.invert && !selection.at_limit() => {
+                    let start = lines[index].start;
+                    let end = lines[group.lines.end - 1].end;
+                    let matched = line_text(&text.as_bytes()[start..end]);
+                    let read = lengths[group.lines.clone()].iter().sum();
+
+                    let span = group.spans[0].start - start..group.spans[0].end - start;
+                    let mut record = self.selected(index + 1, selection.byte_offset, &matched,
+                                                   Some((group.pattern, span, 0)), false);
+                    record.end_line_number = group.lines.end;
+                    if self.replace.is_none() {
+                        // the spans found in the whole text, rather than searching again in only these lines
+                        let spans = group.spans.iter()
+                            .map(|span| span.start - start..(span.end - start).min(matched.len()))
+                            .filter(|span| !span.is_empty());
+                        record.spans = if selection.all_spans { spans.collect() } else { spans.take(1).collect() };
+                    }
+
+                    selection.select(record, read, group.lines.len());
+                    index = group.lines.end;
+                },
+                _ => {
+                    let line = line_text(&text.as_bytes()[lines[index].clone()]);
+                    if self.invert && group.is_none() && !selection.at_limit() {
+                        let record = self.selected(index + 1, selection.byte_offset, &line, None, false);
+                        selection.select(record, lengths[index], 1);
+                    } else {
+                        selection.skip(&line, lengths[index]);
+                    }
+                    index += 1;
+                },
+            }
+
+            if !after(selection)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the record for a selected line, found being its first match (if it matched at all)
+    fn selected(&self, line_number: usize, byte_offset: usize, line: &str,
+                found: Option<(usize, Range<usize>, usize)>, all_spans: bool) -> Record {
+        let mut record = Record {
+            kind: RecordKind::Match,
+            line_number,
+            end_line_number: line_number,
+            byte_offset,
+            line: line.to_string(),
+            spans: Vec::new(),
+            pattern: None,
+            distance: None,
+        };
+
+        let (pattern, span, distance) = match found {
+            Some(found) if !self.invert => found,
+            // an inverted line didn't match, so has nothing to show
+            _ => return record,
+        };
+        record.pattern = Some(pattern);
+        if self.matcher.is_fuzzy() {
+            record.distance = Some(distance);
+        }
+
+        if let Some(ref replacement) = self.replace {
+            let (replaced, spans) = self.matcher.replace(line, replacement);
+            record.line = replaced;
+            record.spans = spans;
+        } else if all_spans {
+            record.spans = self.matcher.find_all(line);
+        } else if !span.is_empty() {
+            record.spans.push(span);
+        }
+
+        record
+    }
+}
+
+// what one search has seen so far, fed a line at a time however the lines are read: which lines
+// are selected, and which are context around them
+struct Selection<'s> {
+    searcher: &'s Searcher,
+    all_spans: bool,
+    line_number: usize,
+    byte_offset: usize,
+    // how many lines have been selected
+    count: usize,
+    // the last few lines that weren't selected, given as before context if a selected line follows
+    before: VecDeque<Record>,
+    // how many more lines of after context to give
+    after_remaining: usize,
+    // records ready to hand out, as a selected line can bring lines of before context with it
+    pending: VecDeque<Record>,
+}
+
+impl<'s> Selection<'s> {
+    fn new(searcher: &'s Searcher, all_spans: bool) -> Selection<'s> {
+        Selection {
+            searcher,
+            all_spans,
+            line_number: 0,
+            byte_offset: 0,
+            count: 0,
+            before: VecDeque::new(),
+            after_remaining: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn at_limit(&self) -> bool {
+        self.searcher.max_count == Some(self.count)
+    }
+
+    // past the limit, lines are only wanted for any after context of the last selected line
+    fn wants_more(&self) -> bool {
+        !self.at_limit() || self.after_remaining > 0
+    }
+
+    // queues up any records for the next line, read being its length with the line ending
+    fn line(&mut self, line: &str, read: usize) {
+        let searcher = self.searcher;
+        let at_limit = self.at_limit();
+        let found = if at_limit { None } else { searcher.matcher.find_match(line) };
+
+        // != on bools is an xor, flipping the result when inverting
+        if !at_limit && found.is_some() != searcher.invert {
+            let record = searcher.selected(self.line_number + 1, self.byte_offset, line, found, self.all_spans);
+            self.select(record, read, 1);
+        } else {
+            self.skip(line, read);
+        }
+    }
+
+    // queues up a selected record, covering lines lines that are read bytes long, after any before
+    // context waiting for it
+    fn select(&mut self, record: Record, read: usize, lines: usize) {
+        self.line_number += lines;
+        self.byte_offset += read;
+        self.count += 1;
+
+        self.pending.extend(self.before.drain(..));
+        self.pending.push_back(record);
+        self.after_remaining = self.searcher.after_context;
+    }
+
+    // a line that isn't selected, which might be context for one that is
+    fn skip(&mut self, line: &str, read: usize) {
+        let searcher = self.searcher;
+        self.line_number += 1;
+        let (line_number, byte_offset) = (self.line_number, self.byte_offset);
+        self.byte_offset += read;
+
+        if self.after_remaining == 0 && searcher.before_context == 0 {
+            return;
+        }
+        let record = Record {
+            kind: RecordKind::Context,
+            line_number,
+            end_line_number: line_number,
+            byte_offset,
+            line: line.to_string(),
+            spans: Vec::new(),
+            pattern: None,
+            distance: None,
+        };
+        if self.after_remaining > 0 {
+            self.after_remaining -= 1;
+            self.pending.push_back(record);
+        } else {
+            if self.before.len() == searcher.before_context {
+                self.before.pop_front();
+            }
+            self.before.push_back(record);
+        }
+    }
+
+    // passes the queued records to sink, returning false if it wants to stop
+    fn send<S: Sink>(&mut self, sink: &mut S) -> io::Result<bool> {
+        while let Some(record) = self.pending.pop_front() {
+            let more = match record.kind {
+                RecordKind::Match => sink.matched(&record)?,
+                RecordKind::Context => sink.context(&record)?,
+            };
+            if !more {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+// the records from one search, see Searcher::search
+pub struct Records<'s, R> {
+    reader: R,
+    // reused for every line read
+    buf: Vec<u8>,
+    selection: Selection<'s>,
+    done: bool,
+}
+
+impl<'s, R: BufRead> Records<'s, R> {
+    // how many lines have been selected so far
+    pub fn selected(&self) -> usize {
+        self.selection.count
+    }
+}
+
+impl<'s, R: BufRead> Iterator for Records<'s, R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        loop {
+            if let Some(record) = self.selection.pending.pop_front() {
+                return Some(Ok(record));
+            }
+            if self.done || !self.selection.wants_more() {
+                return None;
+            }
+
+            let searcher = self.selection.searcher;
+            if searcher.multiline {
+                self.done = true;
+                let result = self.reader.read_to_end(&mut self.buf)
+                    .and_then(|_| searcher.search_multiline(&self.buf, &mut self.selection, |_| Ok(true)));
+                if let Err(e) = result {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            match read_line(&mut self.reader, &mut self.buf) {
+                Ok(Some((read, line))) => self.selection.line(&line, read),
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            }
+        }
+    }
+}
+
+// the lines a multiline match covers, and the matches on them
+struct Group {
+    // line indexes, from 0
+    lines: Range<usize>,
+    // the pattern the first match was for
+    pattern: usize,
+    // where each match is in the whole text
+    spans: Vec<Range<usize>>,
+}
+
+// the byte range of each line in bytes, including its line ending
+fn line_ranges(bytes: &[u8]) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+
+    while start < bytes.len() {
+        let end = memchr(b'\n', &bytes[start..]).map_or(bytes.len(), |newline| start + newline + 1);
+        lines.push(start..end);
+        start = end;
+    }
+
+    lines
+}
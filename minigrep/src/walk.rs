@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::types::TypesBuilder;
+use ignore::{self, WalkBuilder};
+
+use index::INDEX_FILE;
+use Config;
+
+// how many bytes to peek at when deciding if a file is binary, same idea as grep
+const BINARY_PEEK: usize = 8192;
+
+// minigrep's own ignore file, for things to leave out of searches but not out of git
+const IGNORE_FILE: &str = ".minigrepignore";
+
+// walks a directory tree returning every file to search, sorted so output order is stable
+//
+// like git, files matched by .gitignore (or .ignore and .minigrepignore, which use the same
+// syntax) in the directories searched and their parents are skipped, as are hidden files and
+// directories (names starting with a dot), unless --no-ignore or --hidden say otherwise
+//
+// on top of that, include globs (if any) must match a file, exclude globs knock out files or
+// whole directories, and -t and -T pick files by type, e.g. -t rust for *.rs files
+//
+// anything the walk can't read, like a directory without permission to list it, is left out and
+// added to errors, so one bad corner of a big tree doesn't stop the rest being searched
+pub fn files(root: &Path, config: &Config, errors: &mut Vec<ignore::Error>)
+    -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let include = glob_set(&config.include)?;
+    let exclude = glob_set(&config.exclude)?;
+
+    // the ignore crate knows the usual extensions for lots of file types, see --type-list
+    let mut types = TypesBuilder::new();
+    types.add_defaults();
+    for name in &config.types {
+        types.select(name);
+    }
+    for name in &config.types_not {
+        types.negate(name);
+    }
+
+    let ignore_files = !config.no_ignore;
+    let mut walker = WalkBuilder::new(root);
+    walker
+        .hidden(!config.hidden)
+        .ignore(ignore_files)
+        .git_ignore(ignore_files)
+        .git_global(ignore_files)
+        .git_exclude(ignore_files)
+        .parents(ignore_files)
+        // a .gitignore counts outside of a git repo too, as it would for anyone reading it
+        .require_git(false)
+        .types(types.build()?)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    if ignore_files {
+        walker.add_custom_ignore_filename(IGNORE_FILE);
+    }
+
+    // filter_entry stops the walk descending into anything we return false for; the closure has
+    // to own what it uses, as the walker could outlive this function
+    let root_path = root.to_path_buf();
+    walker.filter_entry(move |entry| entry.depth() == 0 || !exclude.is_match(relative(&root_path, entry.path())));
+
+    let mut files = Vec::new();
+    for entry in walker.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            },
+        };
+        // the index isn't one of the files it indexes, even with --hidden
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) || entry.file_name() == INDEX_FILE {
+            continue;
+        }
+        if !include.is_empty() && !include.is_match(relative(root, entry.path())) {
+            continue;
+        }
+        files.push(entry.into_path());
+    }
+
+    Ok(files)
+}
+
+// whether bytes (the start of a file) look binary, having a NUL byte near the start
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_PEEK).any(|b| *b == 0)
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, Box<dyn Error>> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+// globs are matched against the path below the root, so `src/*.rs` works as you'd expect
+fn relative<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
@@ -1,8 +1,14 @@
+extern crate bzip2;
+extern crate flate2;
 extern crate minigrep;
+extern crate tempfile;
+extern crate xz2;
+extern crate zstd;
 
 #[cfg(test)]
 mod test {
     use minigrep::*;
+    use std::ops::Range;
 
     #[test]
     fn valid_config_no_flags() {
@@ -10,17 +16,59 @@ mod test {
         let config = Config::new(&args).unwrap();
 
         assert_eq!("needle", config.query);
-        assert_eq!("haystack", config.filename);
-        assert_eq!(true, config.case_sensitive);
+        assert_eq!(vec!["haystack"], config.filenames);
+        assert!(config.case_sensitive);
     }
 
-    // unsure how to test Err returned from Config::new
-    // #[test]
-    // fn invalid_config() {
-    //     let args = vec!["".to_string()];
-    //     let config = Config::new(&args);
-    //     assert_eq!(config, Err("sss"));
-    // }
+    // Config::new returns a ConfigError enum, so we can match on the error with unwrap_err
+    #[test]
+    fn invalid_config() {
+        let args: Vec<String> = vec![];
+        assert_eq!(ConfigError::MissingQuery, Config::new(&args).unwrap_err());
+
+        let args = vec!["needle".to_string(), "--nope".to_string()];
+        assert_eq!(ConfigError::UnknownFlag("--nope".to_string()), Config::new(&args).unwrap_err());
+
+        let args = vec!["needle".to_string(), "-iQ".to_string()];
+        assert_eq!(ConfigError::UnknownFlag("-Q".to_string()), Config::new(&args).unwrap_err());
+
+        let args = vec!["needle".to_string(), "--regex=yes".to_string()];
+        assert_eq!(ConfigError::UnexpectedValue("--regex=yes".to_string()), Config::new(&args).unwrap_err());
+    }
+
+    #[test]
+    fn config_flags_in_any_position() {
+        let args = vec!["-in".to_string(), "--context=2".to_string(), "needle".to_string(),
+                        "-A3".to_string(), "haystack".to_string(), "--include".to_string(), "*.rs".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert_eq!("needle", config.query);
+        assert_eq!(vec!["haystack"], config.filenames);
+        assert!(!config.case_sensitive);
+        assert!(config.line_number);
+        assert_eq!(2, config.before_context);
+        assert_eq!(3, config.after_context);
+        assert_eq!(vec!["*.rs"], config.include);
+    }
+
+    #[test]
+    fn config_double_dash_ends_flags() {
+        let args = vec!["-n".to_string(), "--".to_string(), "-c".to_string(), "--help".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert_eq!("-c", config.query);
+        assert_eq!(vec!["--help"], config.filenames);
+        assert!(config.line_number);
+    }
+
+    #[test]
+    fn config_help_and_version() {
+        let args = vec!["needle".to_string(), "--help".to_string()];
+        assert_eq!(ConfigError::Help, Config::new(&args).unwrap_err());
+
+        let args = vec!["-V".to_string()];
+        assert_eq!(ConfigError::Version, Config::new(&args).unwrap_err());
+    }
 
     #[test]
     fn case_sensitive() {
@@ -51,4 +99,1719 @@ Duct tape";
             search_case_insensitive(query, contents)
         );
     }
+
+    #[test]
+    fn whole_word() {
+        let query = "duct";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape";
+
+        assert!(search_words(query, contents, true).is_empty());
+        assert_eq!(vec!["Duct tape"], search_words(query, contents, false));
+
+        // a later occurrence can still be a whole word after an earlier one isn't
+        assert_eq!(vec!["productive duct_tape duct"], search_words("duct", "productive duct_tape duct", true));
+
+        // word chars are Unicode aware: accented letters, a combining accent and other scripts
+        // all count as part of the word
+        assert!(search_words("caf", "café", true).is_empty());
+        assert!(search_words("cafe", "cafe\u{301}", true).is_empty());
+        assert!(search_words("東京", "東京都", true).is_empty());
+        assert_eq!(vec!["«straße»"], search_words("STRASSE", "«straße»", false));
+    }
+
+    #[test]
+    fn whole_line() {
+        let query = "duct tape";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape
+Duct tape, again";
+
+        assert!(search_whole_lines(query, contents, true).is_empty());
+        assert_eq!(vec!["Duct tape"], search_whole_lines(query, contents, false));
+        assert!(search_whole_lines("Rust", contents, true).is_empty());
+    }
+
+    #[test]
+    fn boundary_flags() {
+        let args: Vec<String> = ["-w", "duct", "poem.txt"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(Boundary::Word, Config::new(&args).unwrap().boundary);
+
+        let args: Vec<String> = ["--line-regexp", "duct", "poem.txt"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(Boundary::Line, Config::new(&args).unwrap().boundary);
+
+        // the regex is anchored to the whole line, so the longer alternative still gets tried
+        let patterns = vec!["a|ab".to_string()];
+        let matcher = Matcher::with_boundary(&patterns, true, true, Boundary::Line).unwrap();
+        assert_eq!(Some(0..2), matcher.find("ab"));
+
+        // and to the word for -w, with matches that don't start or end on a word char still found
+        let matcher = Matcher::with_boundary(&patterns, true, true, Boundary::Word).unwrap();
+        assert_eq!(Some(0..2), matcher.find("ab"));
+        assert_eq!(vec![2..4], matcher.find_all("x ab abc"));
+        let patterns = vec![r"c\+\+".to_string(), "dc|duct".to_string()];
+        let matcher = Matcher::with_boundary(&patterns, false, true, Boundary::Word).unwrap();
+        assert_eq!(vec![0..3, 4..8], matcher.find_all("C++ duct productive"));
+        assert_eq!(("tape tape productive".to_string(), vec![0..4, 5..9]), matcher.replace("C++ duct productive", "tape"));
+
+        let patterns = vec!["duct".to_string(), "tape".to_string()];
+        let matcher = Matcher::with_boundary(&patterns, false, false, Boundary::Word).unwrap();
+        assert_eq!(vec![0..4, 5..9], matcher.find_all("Duct tape productive"));
+    }
+
+    #[test]
+    fn whole_word_output() {
+        let (code, stdout) = run_output_mode(&["-iw", "duct", "poem.txt"]);
+        assert_eq!(Some(0), code);
+        assert_eq!("Duct tape\n", stdout);
+
+        let (code, stdout) = run_output_mode(&["-x", "Pick three.", "poem.txt"]);
+        assert_eq!(Some(0), code);
+        assert_eq!("Pick three.\n", stdout);
+
+        let (code, _) = run_output_mode(&["-x", "Pick", "poem.txt"]);
+        assert_eq!(Some(1), code);
+    }
+
+    #[test]
+    fn valid_config_regex_flag() {
+        let args = vec!["^Pick".to_string(), "haystack".to_string(), "-E".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert!(config.regex);
+    }
+
+    #[test]
+    fn regex() {
+        let query = r"^[a-z]+, \w+";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape";
+
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            search_regex(query, contents, true).unwrap()
+        );
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let query = "^(pick|duct) t";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape";
+
+        assert_eq!(
+            vec!["Pick three.", "Duct tape"],
+            search_regex(query, contents, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn regex_invalid_pattern() {
+        assert!(search_regex("ERROR [0-9", "ERROR 500", true).is_err());
+    }
+
+    #[test]
+    fn valid_config_globs() {
+        let args = vec!["needle".to_string(), "src".to_string(),
+                        "--include=*.rs".to_string(), "--exclude=target".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert_eq!(vec!["*.rs"], config.include);
+        assert_eq!(vec!["target"], config.exclude);
+    }
+
+    #[test]
+    fn walk_directory() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("src/lib.rs"), "safe, fast, productive.").unwrap();
+        fs::write(root.join("src/nested/notes.txt"), "Duct tape").unwrap();
+        fs::write(root.join(".git/config"), "hidden").unwrap();
+        fs::write(root.join("target/out.rs"), "built").unwrap();
+
+        let files = walk::files(root, &walk_config(&["--exclude=target"]), &mut Vec::new()).unwrap();
+        assert_eq!(vec![root.join("src/lib.rs"), root.join("src/nested/notes.txt")], files);
+
+        let files = walk::files(root, &walk_config(&["--include=*.txt"]), &mut Vec::new()).unwrap();
+        assert_eq!(vec![root.join("src/nested/notes.txt")], files);
+    }
+
+    // a config with the given flags, for walk::files
+    fn walk_config(flags: &[&str]) -> Config {
+        let mut args = vec!["needle".to_string()];
+        args.extend(flags.iter().map(|flag| flag.to_string()));
+        Config::new(&args).unwrap()
+    }
+
+    #[test]
+    fn walk_honours_ignore_files() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::create_dir_all(root.join("vendor")).unwrap();
+        fs::write(root.join("src/lib.rs"), "safe, fast, productive.").unwrap();
+        fs::write(root.join("src/app.js"), "Duct tape").unwrap();
+        fs::write(root.join("target/out.rs"), "built").unwrap();
+        fs::write(root.join("logs/today.log"), "Pick three.").unwrap();
+        fs::write(root.join("vendor/lib.rs"), "theirs").unwrap();
+        fs::write(root.join(".env"), "hidden").unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join(".ignore"), "vendor/\n").unwrap();
+        fs::write(root.join(".minigrepignore"), "*.log\n").unwrap();
+
+        let files = walk::files(root, &walk_config(&[]), &mut Vec::new()).unwrap();
+        assert_eq!(vec![root.join("src/app.js"), root.join("src/lib.rs")], files);
+
+        let files = walk::files(root, &walk_config(&["--hidden"]), &mut Vec::new()).unwrap();
+        assert_eq!(vec![root.join(".env"), root.join(".gitignore"), root.join(".ignore"),
+                        root.join(".minigrepignore"), root.join("src/app.js"), root.join("src/lib.rs")], files);
+
+        let files = walk::files(root, &walk_config(&["--no-ignore"]), &mut Vec::new()).unwrap();
+        assert_eq!(vec![root.join("logs/today.log"), root.join("src/app.js"), root.join("src/lib.rs"),
+                        root.join("target/out.rs"), root.join("vendor/lib.rs")], files);
+
+        let files = walk::files(root, &walk_config(&["-t", "rust"]), &mut Vec::new()).unwrap();
+        assert_eq!(vec![root.join("src/lib.rs")], files);
+
+        let files = walk::files(root, &walk_config(&["--no-ignore", "-T", "rust"]), &mut Vec::new()).unwrap();
+        assert_eq!(vec![root.join("logs/today.log"), root.join("src/app.js")], files);
+
+        assert!(walk::files(root, &walk_config(&["-t", "nope"]), &mut Vec::new()).is_err());
+        assert_eq!(ConfigError::TypeList, Config::new(&["--type-list".to_string()]).unwrap_err());
+    }
+
+    // a directory that can't be listed is reported, and the rest of the tree is still searched
+    #[cfg(unix)]
+    #[test]
+    fn run_unreadable_directory() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("b.txt"), "needle\n").unwrap();
+        fs::write(dir.path().join("a.txt"), "needle\n").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        // root can read it anyway, so there's nothing to test
+        let readable = fs::read_dir(&locked).is_ok();
+        let mut errors = Vec::new();
+        let files = walk::files(dir.path(), &walk_config(&[]), &mut errors).unwrap();
+        let output = minigrep().current_dir(dir.path()).args(["needle", "."]).output().unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            return;
+        }
+
+        assert_eq!(vec![dir.path().join("a.txt")], files);
+        assert_eq!(1, errors.len());
+        assert_eq!(Some(2), output.status.code());
+        assert_eq!("./a.txt:needle\n", String::from_utf8(output.stdout).unwrap());
+        assert_eq!("minigrep: ./locked: Permission denied (os error 13)\n", String::from_utf8(output.stderr).unwrap());
+    }
+
+    #[test]
+    fn walk_skips_binary_files() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("text"), "Pick three.").unwrap();
+        fs::write(dir.path().join("binary"), b"Pick\0three.").unwrap();
+
+        assert!(!walk::is_binary(b"Pick three."));
+        assert!(walk::is_binary(b"Pick\0three."));
+
+        // a binary file found in a directory is left out of the search altogether
+        let output = minigrep().current_dir(dir.path()).args(["three", "."]).output().unwrap();
+        assert_eq!("./text:Pick three.\n", String::from_utf8(output.stdout).unwrap());
+    }
+
+    #[test]
+    fn valid_config_many_files() {
+        let args = vec!["needle".to_string(), "one".to_string(), "-i".to_string(),
+                        "two".to_string(), "-".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert_eq!(vec!["one", "two", "-"], config.filenames);
+        assert!(!config.case_sensitive);
+    }
+
+    #[test]
+    fn valid_config_no_files_reads_stdin() {
+        let args = vec!["needle".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert_eq!(vec![STDIN], config.filenames);
+    }
+
+    #[test]
+    fn run_from_stdin_and_files() {
+        use std::fs;
+        use std::io::Write;
+        use std::process::Stdio;
+
+        let dir = tempfile::tempdir().unwrap();
+        let poem = dir.path().join("poem.txt");
+        fs::write(&poem, "Rust:\nsafe, fast, productive.\n").unwrap();
+
+        let mut child = minigrep()
+            .args(["duct", "-", poem.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"Duct tape\nproduct\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        let expected = format!("(standard input):product\n{}:safe, fast, productive.\n", poem.display());
+        assert_eq!(expected, String::from_utf8(output.stdout).unwrap());
+    }
+
+    // a file that can't be opened is reported, the others are still searched, and the exit status
+    // says something went wrong
+    #[test]
+    fn run_missing_file() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("poem.txt"), "Rust:\nsafe, fast, productive.\n").unwrap();
+
+        for threads in &["1", "2"] {
+            let output = minigrep()
+                .current_dir(dir.path())
+                .args(["-j", threads, "-n", "duct", "missing.txt", "poem.txt", "gone.txt"])
+                .output()
+                .unwrap();
+            assert_eq!(Some(2), output.status.code());
+            assert_eq!("poem.txt:2:safe, fast, productive.\n", String::from_utf8(output.stdout).unwrap());
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            let lines: Vec<&str> = stderr.lines().map(|line| &line[..line.find(": No such").unwrap()]).collect();
+            assert_eq!(vec!["minigrep: missing.txt", "minigrep: gone.txt"], lines);
+        }
+
+        // with -q, a selected line is still the answer
+        let output = minigrep().current_dir(dir.path()).args(["-q", "duct", "missing.txt", "poem.txt"]).output().unwrap();
+        assert_eq!(Some(0), output.status.code());
+    }
+
+    #[test]
+    fn valid_config_context() {
+        let args = vec!["needle".to_string(), "-n".to_string(), "-C".to_string(), "2".to_string(),
+                        "-A".to_string(), "1".to_string(), "haystack".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert!(config.line_number);
+        assert_eq!(2, config.before_context);
+        assert_eq!(1, config.after_context);
+        assert_eq!(vec!["haystack"], config.filenames);
+    }
+
+    #[test]
+    fn invalid_config_context() {
+        let args = vec!["needle".to_string(), "-A".to_string(), "many".to_string()];
+        assert_eq!(
+            ConfigError::InvalidValue("-A".to_string(), "many".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+
+        let args = vec!["needle".to_string(), "-B".to_string()];
+        assert_eq!(ConfigError::MissingValue("-B".to_string()), Config::new(&args).unwrap_err());
+    }
+
+    #[test]
+    fn match_records() {
+        let searcher = SearcherBuilder::new().pattern("duct").case(Case::Insensitive).build().unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape";
+
+        let found: Vec<(usize, usize, Range<usize>, usize, String)> = searcher.search(contents.as_bytes())
+            .map(|record| record.unwrap())
+            .map(|record| (record.line_number, record.byte_offset, record.spans[0].clone(), record.pattern.unwrap(),
+                           record.line))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, 6, 15..19, 0, "safe, fast, productive.".to_string()),
+                (4, 42, 0..4, 0, "Duct tape".to_string()),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn run_with_context() {
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lines.txt");
+        let contents: Vec<String> = (1..=10).map(|n| format!("line {}", n)).collect();
+        std::fs::write(&file, contents.join("\n")).unwrap();
+
+        let output = minigrep()
+            .args(["line (2|3|8)$", file.to_str().unwrap(), "-E", "-n", "-C", "1"])
+            .output()
+            .unwrap();
+
+        let expected = "\
+1-line 1
+2:line 2
+3:line 3
+4-line 4
+--
+7-line 7
+8:line 8
+9-line 9
+";
+        assert_eq!(expected, String::from_utf8(output.stdout).unwrap());
+    }
+
+    #[test]
+    fn search_streams_records() {
+        use std::io::Cursor;
+
+        let searcher = SearcherBuilder::new().pattern("duct").case(Case::Insensitive).build().unwrap();
+        let contents = Cursor::new(&b"Rust:\r\nsafe, fast, productive.\nPick \xff three.\nDuct tape \xfe"[..]);
+
+        let found: Vec<(usize, usize, Range<usize>, String)> = searcher.search(contents)
+            .map(|record| record.unwrap())
+            .map(|record| (record.line_number, record.byte_offset, record.spans[0].clone(), record.line))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (2, 7, 15..19, "safe, fast, productive.".to_string()),
+                (4, 45, 0..4, "Duct tape \u{fffd}".to_string()),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn valid_config_output_modes() {
+        let args = vec!["needle".to_string(), "-vcm".to_string(), "5".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert!(config.invert);
+        assert_eq!(OutputMode::Count, config.mode);
+        assert_eq!(Some(5), config.max_count);
+
+        let args = vec!["needle".to_string(), "-s".to_string(), "-q".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert!(config.case_sensitive);
+        assert_eq!(OutputMode::Quiet, config.mode);
+    }
+
+    // the minigrep binary, kept away from any config file or CASE_INSENSITIVE set by whoever is
+    // running the tests
+    fn minigrep() -> std::process::Command {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_minigrep"));
+        command.env("MINIGREP_CONFIG", "").env_remove("CASE_INSENSITIVE");
+        command
+    }
+
+    // runs the minigrep binary over two files, one with "Duct tape" in and one without
+    fn run_output_mode(args: &[&str]) -> (Option<i32>, String) {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("poem.txt"), "Rust:\nsafe, fast, productive.\nPick three.\nDuct tape").unwrap();
+        fs::write(dir.path().join("empty.txt"), "nothing here\n").unwrap();
+
+        let output = minigrep()
+            .current_dir(dir.path())
+            .args(args)
+            .output()
+            .unwrap();
+
+        (output.status.code(), String::from_utf8(output.stdout).unwrap())
+    }
+
+    #[test]
+    fn run_output_modes() {
+        assert_eq!((Some(0), "poem.txt:2\nempty.txt:0\n".to_string()),
+                   run_output_mode(&["-ic", "duct", "poem.txt", "empty.txt"]));
+        assert_eq!((Some(0), "poem.txt\n".to_string()),
+                   run_output_mode(&["-l", "Duct", "poem.txt", "empty.txt"]));
+        // like GNU grep, the exit status still says whether any line was selected
+        assert_eq!((Some(0), "empty.txt\n".to_string()),
+                   run_output_mode(&["-L", "Duct", "poem.txt", "empty.txt"]));
+        assert_eq!((Some(0), "Rust:\nPick three.\n".to_string()),
+                   run_output_mode(&["-v", "duct", "poem.txt", "-im", "2"]));
+        assert_eq!((Some(0), "".to_string()),
+                   run_output_mode(&["-q", "Duct", "poem.txt", "empty.txt"]));
+        assert_eq!((Some(1), "".to_string()),
+                   run_output_mode(&["-q", "Rust", "empty.txt"]));
+        assert_eq!((Some(2), "".to_string()),
+                   run_output_mode(&["Rust", "missing.txt"]));
+    }
+
+    #[test]
+    fn run_json_output() {
+        let (code, stdout) = run_output_mode(&["--json", "-i", "duct", "poem.txt", "empty.txt"]);
+        assert_eq!(Some(0), code);
+
+        let expected = vec![
+            r#"{"type":"begin","file":"poem.txt"}"#,
+            r#"{"type":"match","file":"poem.txt","line_number":2,"byte_offset":6,"submatches":[{"start":15,"end":19,"text":"duct"}],"line":"safe, fast, productive."}"#,
+            r#"{"type":"match","file":"poem.txt","line_number":4,"byte_offset":42,"submatches":[{"start":0,"end":4,"text":"Duct"}],"line":"Duct tape"}"#,
+            r#"{"type":"end","file":"poem.txt","matches":2}"#,
+            r#"{"type":"begin","file":"empty.txt"}"#,
+            r#"{"type":"end","file":"empty.txt","matches":0}"#,
+            r#"{"type":"summary","files":2,"files_with_matches":1,"matches":2}"#,
+        ];
+        assert_eq!(expected, stdout.lines().collect::<Vec<_>>());
+
+        // searching in parallel gives the same records, and the same summary
+        let (_, parallel) = run_output_mode(&["--json", "-i", "-j", "2", "duct", "poem.txt", "empty.txt"]);
+        assert_eq!(stdout, parallel);
+    }
+
+    #[test]
+    fn run_json_escapes_and_context() {
+        let (_, stdout) = run_output_mode(&["--json", "-A", "1", "-E", "Pick", "poem.txt"]);
+        let lines: Vec<&str> = stdout.lines().collect();
+
+        assert_eq!(
+            r#"{"type":"context","file":"poem.txt","line_number":4,"byte_offset":42,"submatches":[],"line":"Duct tape"}"#,
+            lines[2]
+        );
+
+        // quotes, backslashes and control chars in the text are escaped
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("quoted.txt"), "say \"hi\"\tC:\\ \u{1}\n").unwrap();
+        let output = minigrep()
+            .current_dir(dir.path())
+            .args(["--json", "hi", "quoted.txt"])
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains(r#""line":"say \"hi\"\tC:\\ \u0001"}"#));
+    }
+
+    #[test]
+    fn replace_matches() {
+        let matcher = Matcher::new("duct", false, false).unwrap();
+        let (replaced, spans) = matcher.replace("Duct tape duct", "Tape");
+        assert_eq!("Tape tape Tape", replaced);
+        assert_eq!(vec![0..4, 10..14], spans);
+
+        // capture groups can be used in regex mode, by number or by name
+        let matcher = Matcher::new(r"(\w+), (?P<second>\w+)", true, true).unwrap();
+        assert_eq!("fast safe, productive.",
+                   matcher.replace("safe, fast, productive.", "$second $1").0);
+
+        // with -w only whole words are replaced
+        let patterns = vec!["duct".to_string()];
+        let matcher = Matcher::with_boundary(&patterns, true, false, Boundary::Word).unwrap();
+        assert_eq!("productive tape", matcher.replace("productive duct", "tape").0);
+
+        // empty matches are replaced too, but not straight after another match
+        let matcher = Matcher::new("a*", true, true).unwrap();
+        assert_eq!("XbXcX", matcher.replace("baac", "X").0);
+    }
+
+    #[test]
+    fn run_replace() {
+        let (code, stdout) = run_output_mode(&["-i", "--replace", "tape", "duct", "poem.txt"]);
+        assert_eq!(Some(0), code);
+        assert_eq!("safe, fast, protapeive.\ntape tape\n", stdout);
+
+        let (_, stdout) = run_output_mode(&["-E", "-r", "$2 $1", r"^(\w+) (\w+)", "poem.txt"]);
+        assert_eq!("three Pick.\ntape Duct\n", stdout);
+
+        let args = vec!["--in-place".to_string(), "duct".to_string()];
+        assert_eq!(
+            ConfigError::Requires("--in-place".to_string(), "--replace".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn run_replace_in_place() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "Rust:\r\nsafe, fast, productive.\r\nPick three.\nDuct tape").unwrap();
+
+        let run = |args: &[&str]| {
+            minigrep()
+                .current_dir(dir.path())
+                .args(args)
+                .output()
+                .unwrap()
+        };
+
+        // a dry run only prints a diff of what would change
+        let output = run(&["-i", "--in-place", "--dry-run", "-r", "tape", "duct", "poem.txt"]);
+        assert_eq!(Some(0), output.status.code());
+        assert_eq!("\
+--- poem.txt
++++ poem.txt
+@@ -2,1 +2,1 @@
+-safe, fast, productive.\r
++safe, fast, protapeive.\r
+@@ -4,1 +4,1 @@
+-Duct tape
+\\ No newline at end of file
++tape tape
+\\ No newline at end of file
+", String::from_utf8(output.stdout).unwrap());
+        assert_eq!("Rust:\r\nsafe, fast, productive.\r\nPick three.\nDuct tape", fs::read_to_string(&path).unwrap());
+
+        // line endings are left as they were, and no temp file is left behind
+        let output = run(&["-i", "--in-place", "-r", "tape", "duct", "poem.txt"]);
+        assert_eq!(Some(0), output.status.code());
+        assert!(output.stdout.is_empty());
+        assert_eq!("Rust:\r\nsafe, fast, protapeive.\r\nPick three.\ntape tape", fs::read_to_string(&path).unwrap());
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+
+        let output = run(&["--in-place", "-r", "tape", "nowhere", "poem.txt"]);
+        assert_eq!(Some(1), output.status.code());
+
+        // a walked file that isn't UTF-8 is skipped, like a binary one, and the rest rewritten
+        fs::create_dir(dir.path().join("ip")).unwrap();
+        fs::write(dir.path().join("ip/a.txt"), "old\n").unwrap();
+        fs::write(dir.path().join("ip/b.txt"), b"caf\xe9 old\n").unwrap();
+        fs::write(dir.path().join("ip/c.txt"), "old\n").unwrap();
+        let output = run(&["--in-place", "-r", "new", "old", "ip"]);
+        assert_eq!(Some(0), output.status.code());
+        assert_eq!("new\n", fs::read_to_string(dir.path().join("ip/c.txt")).unwrap());
+        assert_eq!(b"caf\xe9 old\n".to_vec(), fs::read(dir.path().join("ip/b.txt")).unwrap());
+
+        // one named on the command line is reported, and the others are still rewritten
+        let output = run(&["--in-place", "-r", "old", "new", "ip/b.txt", "ip/missing.txt", "ip/c.txt"]);
+        assert_eq!(Some(2), output.status.code());
+        assert_eq!("old\n", fs::read_to_string(dir.path().join("ip/c.txt")).unwrap());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with("minigrep: ip/b.txt: invalid utf-8"));
+        assert!(stderr.contains("\nminigrep: ip/missing.txt: No such file"));
+
+        // every match is replaced, so there are no lines for -v or -m to leave alone
+        let args: Vec<String> = ["-v", "-r", "new", "--in-place", "old", "v.txt"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            ConfigError::Conflicts("--invert-match".to_string(), "--in-place".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+        let args: Vec<String> = ["-m1", "-r", "new", "--in-place", "old", "v.txt"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            ConfigError::Conflicts("--max-count".to_string(), "--in-place".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn valid_config_color() {
+        let args = vec!["needle".to_string()];
+        assert_eq!(ColorChoice::Auto, Config::new(&args).unwrap().color);
+
+        let args = vec!["needle".to_string(), "--color=always".to_string()];
+        assert_eq!(ColorChoice::Always, Config::new(&args).unwrap().color);
+
+        let args = vec!["needle".to_string(), "--color".to_string(), "sometimes".to_string()];
+        assert_eq!(
+            ConfigError::InvalidValue("--color".to_string(), "sometimes".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn find_all_spans() {
+        let matcher = Matcher::new("duct", false, false).unwrap();
+        assert_eq!(vec![0..4, 5..9], matcher.find_all("Duct duct tape"));
+
+        // empty regex matches have nothing to highlight
+        let matcher = Matcher::new("^", true, true).unwrap();
+        assert!(matcher.find_all("Duct tape").is_empty());
+    }
+
+    #[test]
+    fn run_with_color() {
+        assert_eq!(
+            (Some(0), "\x1b[32m4\x1b[0m\x1b[36m:\x1b[0m\x1b[1;31mDuct\x1b[0m tape\n".to_string()),
+            run_output_mode(&["-n", "--color=always", "Duct", "poem.txt"])
+        );
+
+        // auto never colors here, as the test's stdout is a pipe rather than a terminal
+        assert_eq!(
+            (Some(0), "Duct tape\n".to_string()),
+            run_output_mode(&["--color=auto", "Duct", "poem.txt"])
+        );
+    }
+
+    #[test]
+    fn valid_config_threads() {
+        let args = vec!["needle".to_string(), "-j4".to_string(), "--sort-files".to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert_eq!(4, config.threads);
+        assert!(config.sort_files);
+
+        let args = vec!["needle".to_string(), "-j0".to_string()];
+        assert_eq!(
+            ConfigError::InvalidValue("-j".to_string(), "0".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn matcher_shared_between_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let matcher = Arc::new(Matcher::new("duct", false, false).unwrap());
+        let handles: Vec<_> = vec!["productive", "Duct tape", "Pick three"].into_iter().map(|line| {
+            let matcher = Arc::clone(&matcher);
+            thread::spawn(move || matcher.find_all(line).len())
+        }).collect();
+
+        let counts: Vec<usize> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(vec![1, 1, 0], counts);
+    }
+
+    #[test]
+    fn run_in_parallel_keeps_order() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut names = Vec::new();
+        for n in 0..20 {
+            // earlier files are bigger, so they tend to finish last
+            let lines: Vec<String> = (0..(20 - n) * 500).map(|i| format!("file {} line {}", n, i)).collect();
+            let name = format!("{:02}.txt", n);
+            fs::write(dir.path().join(&name), lines.join("\n")).unwrap();
+            names.push(name);
+        }
+        // given out of order, so --sort-files has something to do
+        names.reverse();
+
+        let minigrep = |extra: &[&str]| {
+            let output = minigrep()
+                .current_dir(dir.path())
+                .args(["-n", "-C", "1", "line 99"])
+                .args(extra)
+                .args(&names)
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        let sequential = minigrep(&[]);
+        assert_eq!(sequential, minigrep(&["-j", "8"]));
+
+        let sorted = minigrep(&["-j", "8", "--sort-files"]);
+        assert!(sorted.starts_with("00.txt-99-file 0 line 98\n"));
+        assert_eq!(sequential.len(), sorted.len());
+    }
+
+    #[test]
+    fn case_insensitive_unicode_folding() {
+        let contents = "\
+Straße
+STRASSE
+strasse
+Kelvin: 3\u{212a}";
+
+        assert_eq!(vec!["Straße", "STRASSE", "strasse"], search_case_insensitive("strasse", contents));
+        assert_eq!(vec!["Straße", "STRASSE", "strasse"], search_case_insensitive("STRAẞE", contents));
+        assert_eq!(vec!["Kelvin: 3\u{212a}"], search_case_insensitive("3k", contents));
+
+        // matching has to end on a whole char of the line, so "s" alone doesn't match half of ß
+        assert!(search_case_insensitive("aßs", "aß").is_empty());
+    }
+
+    #[test]
+    fn case_insensitive_spans_in_original_line() {
+        let matcher = Matcher::new("ss", false, false).unwrap();
+        assert_eq!(vec![4..6, 8..10], matcher.find_all("Straße SS"));
+
+        // İ folds to two chars, i and a combining dot, so plain i doesn't match it
+        let matcher = Matcher::new("istanbul", false, false).unwrap();
+        assert_eq!(None, matcher.find("İstanbul"));
+        assert_eq!(Some(0..8), matcher.find("ISTANBUL,"));
+    }
+
+    #[test]
+    fn smart_case() {
+        let args = vec!["duct".to_string(), "-S".to_string()];
+        let matcher = Matcher::from_config(&Config::new(&args).unwrap()).unwrap();
+        assert!(matcher.find("Duct tape").is_some());
+
+        let args = vec!["Duct".to_string(), "-S".to_string()];
+        let matcher = Matcher::from_config(&Config::new(&args).unwrap()).unwrap();
+        assert!(matcher.find("duct tape").is_none());
+
+        // the \S escape isn't an uppercase letter
+        let args = vec![r"duct\S".to_string(), "-SE".to_string()];
+        let matcher = Matcher::from_config(&Config::new(&args).unwrap()).unwrap();
+        assert!(matcher.find("Ducts").is_some());
+
+        // neither is the class name of a \p{..} escape, but a letter after it still counts
+        let args = vec![r"\p{Lu}uct".to_string(), "-SE".to_string()];
+        let matcher = Matcher::from_config(&Config::new(&args).unwrap()).unwrap();
+        assert!(matcher.find("DUCT tape").is_some());
+
+        let args = vec![r"\pLUct".to_string(), "-SE".to_string()];
+        let matcher = Matcher::from_config(&Config::new(&args).unwrap()).unwrap();
+        assert!(matcher.find("duct tape").is_none());
+    }
+
+    #[test]
+    fn valid_config_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let patterns = dir.path().join("patterns.txt");
+        std::fs::write(&patterns, "tape\nthree\n").unwrap();
+
+        let args = vec!["-e".to_string(), "duct".to_string(), "poem.txt".to_string(),
+                        "-f".to_string(), patterns.to_str().unwrap().to_string()];
+        let config = Config::new(&args).unwrap();
+
+        assert_eq!(vec!["duct", "tape", "three"], config.patterns);
+        assert_eq!("duct", config.query);
+        assert_eq!(vec!["poem.txt"], config.filenames);
+
+        let args = vec!["-f".to_string(), "missing.txt".to_string()];
+        match Config::new(&args).unwrap_err() {
+            ConfigError::PatternFile(filename, _) => assert_eq!("missing.txt", filename),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn multi_pattern_search() {
+        let patterns = vec!["three".to_string(), "duct".to_string(), "productive".to_string()];
+        let mut builder = SearcherBuilder::new();
+        for pattern in &patterns {
+            builder.pattern(pattern);
+        }
+        let searcher = builder.build().unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape";
+
+        let found: Vec<(usize, usize, Range<usize>)> = searcher.search(contents.as_bytes())
+            .map(|record| record.unwrap())
+            .map(|record| (record.line_number, record.pattern.unwrap(), record.spans[0].clone()))
+            .collect();
+
+        // productive and duct both match line 2, but productive starts further left
+        assert_eq!(vec![(2, 2, 12..22), (3, 0, 5..10)], found);
+    }
+
+    #[test]
+    fn multi_pattern_case_insensitive() {
+        let patterns = vec!["strasse".to_string(), "tape".to_string(), "ape".to_string()];
+        let matcher = Matcher::with_patterns(&patterns, false, false).unwrap();
+
+        assert_eq!(Some((0, 0..7)), matcher.find_pattern("Straße"));
+        assert_eq!(vec![5..9], matcher.find_all("Duct TAPE"));
+        assert_eq!(None, matcher.find_pattern("Pick three."));
+    }
+
+    #[test]
+    fn multi_pattern_find_from() {
+        use minigrep::multi::{MultiPattern, PatternMatch};
+
+        let patterns = vec!["bc".to_string(), "abcd".to_string(), "cd".to_string(), "strasse".to_string()];
+        let multi = MultiPattern::new(&patterns, false);
+
+        // bc ends first, but abcd starts further left
+        assert_eq!(Some(PatternMatch { pattern: 1, span: 1..5 }), multi.find_from("xabcdx", 0));
+        // starting part way through, only what starts there or later counts
+        assert_eq!(Some(PatternMatch { pattern: 0, span: 2..4 }), multi.find_from("xabcdx", 2));
+        assert_eq!(Some(PatternMatch { pattern: 2, span: 3..5 }), multi.find_from("xabcdx", 3));
+        assert_eq!(None, multi.find_from("xabcdx", 4));
+        assert_eq!(Some(PatternMatch { pattern: 3, span: 9..16 }), multi.find_from("Straße, Straße", 1));
+        assert_eq!(Some(PatternMatch { pattern: 1, span: 1..5 }), multi.find_from("xABCDxBC", 0));
+        assert_eq!(Some(PatternMatch { pattern: 0, span: 6..8 }), multi.find_from("xABCDxBC", 5));
+    }
+
+    #[test]
+    fn multi_pattern_regex() {
+        let patterns = vec![r"ERROR \d{3}".to_string(), "^WARN".to_string()];
+        let matcher = Matcher::with_patterns(&patterns, true, true).unwrap();
+
+        assert_eq!(Some((1, 0..4)), matcher.find_pattern("WARN disk at 90%"));
+        assert_eq!(Some((0, 6..15)), matcher.find_pattern("12:00 ERROR 500"));
+        assert_eq!(None, matcher.find_pattern("INFO WARN ERROR"));
+    }
+
+    #[test]
+    fn run_show_pattern() {
+        assert_eq!(
+            (Some(0), "2:productive:safe, fast, productive.\n4:tape:Duct tape\n".to_string()),
+            run_output_mode(&["-n", "--show-pattern", "-e", "productive", "-e", "tape", "poem.txt"])
+        );
+    }
+
+    #[test]
+    fn fuzzy_matching() {
+        use minigrep::fuzzy::{FuzzyMatch, FuzzyQuery};
+
+        let query = FuzzyQuery::new("prodct", 1, true).unwrap();
+        assert_eq!(Some(FuzzyMatch { span: 12..19, distance: 1 }), query.find("safe, fast, productive."));
+        assert_eq!(None, query.find("Pick three."));
+
+        // of the matches as close as each other, the one most like the query
+        let query = FuzzyQuery::new("dcut", 2, false).unwrap();
+        assert_eq!(Some(FuzzyMatch { span: 0..4, distance: 2 }), query.find("Duct tape"));
+        assert_eq!(Some(FuzzyMatch { span: 2..6, distance: 0 }), query.find("a dcut"));
+
+        let too_long = "x".repeat(fuzzy::MAX_FUZZY_LEN + 1);
+        assert!(FuzzyQuery::new(&too_long, 1, true).is_err());
+    }
+
+    // the smallest edit distance between the query and any substring of the line, the slow way
+    fn naive_distance(query: &str, line: &str) -> usize {
+        let query: Vec<char> = query.chars().collect();
+        // the first row is all 0, as a match can start anywhere
+        let mut row = vec![0; line.chars().count() + 1];
+
+        for (i, &q) in query.iter().enumerate() {
+            let mut next = vec![i + 1];
+            for (j, c) in line.chars().enumerate() {
+                let substitute = row[j] + if q == c { 0 } else { 1 };
+                next.push(substitute.min(row[j + 1] + 1).min(next[j] + 1));
+            }
+            row = next;
+        }
+
+        *row.iter().min().unwrap()
+    }
+
+    #[test]
+    fn fuzzy_distance_agrees_with_naive() {
+        use minigrep::fuzzy::FuzzyQuery;
+
+        let queries = ["duct", "prodct", "three", "pikc", "Rust:", "ütf", ""];
+        let lines = ["Rust:", "safe, fast, productive.", "Pick three.", "Duct tape", "ütf-8 ütf", ""];
+
+        for query in &queries {
+            for line in &lines {
+                let expected = naive_distance(query, line);
+                let found = FuzzyQuery::new(query, 3, true).unwrap().find(line);
+                if expected > 3 {
+                    assert_eq!(None, found, "{:?} in {:?}", query, line);
+                    continue;
+                }
+
+                let found = found.unwrap();
+                assert_eq!(expected, found.distance, "{:?} in {:?}", query, line);
+                // and the span really is that many edits away
+                assert_eq!(expected, naive_distance(query, &line[found.span.clone()]));
+            }
+        }
+    }
+
+    #[test]
+    fn run_fuzzy() {
+        assert_eq!(
+            (Some(0), "2:~1:safe, fast, productive.\n".to_string()),
+            run_output_mode(&["-n", "--fuzzy", "1", "prodct", "poem.txt"])
+        );
+        assert_eq!((Some(0), "~1:Duct tape\n".to_string()), run_output_mode(&["--fuzzy=1", "-iw", "ducts", "poem.txt"]));
+        let (_, json) = run_output_mode(&["--json", "--fuzzy", "1", "prodct", "poem.txt"]);
+        assert!(json.contains(r#""submatches":[{"start":12,"end":19,"text":"product"}],"distance":1,"#), "{}", json);
+
+        let args = strings(&["--fuzzy", "1", "--regex", "needle", "haystack"]);
+        assert_eq!(
+            ConfigError::Conflicts("--fuzzy".to_string(), "--regex".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn searcher_records() {
+        let contents = "Rust:\nsafe, fast, productive.\nPick three.\nDuct tape.\nTrust me.";
+        let searcher = SearcherBuilder::new()
+            .pattern("duct")
+            .case(Case::Insensitive)
+            .after_context(1)
+            .build()
+            .unwrap();
+
+        let records: Vec<Record> = searcher.search(contents.as_bytes()).map(|record| record.unwrap()).collect();
+        let found: Vec<(RecordKind, usize, &str)> = records.iter()
+            .map(|record| (record.kind, record.line_number, record.line.as_str()))
+            .collect();
+        assert_eq!(vec![
+            (RecordKind::Match, 2, "safe, fast, productive."),
+            (RecordKind::Context, 3, "Pick three."),
+            (RecordKind::Match, 4, "Duct tape."),
+            (RecordKind::Context, 5, "Trust me."),
+        ], found);
+        assert_eq!([15..19, 0..4], [records[0].spans[0].clone(), records[2].spans[0].clone()]);
+        assert!(records[1].spans.is_empty());
+        assert_eq!(Some(0), records[0].pattern);
+        assert_eq!(6, records[0].byte_offset);
+
+        // a bad regex is an error from build
+        assert!(SearcherBuilder::new().pattern("(").regex(true).build().is_err());
+    }
+
+    // collects the line numbers it's given, and stops the search after a number of matches
+    struct Collect {
+        lines: Vec<(usize, bool)>,
+        stop_after: usize,
+    }
+
+    impl Sink for Collect {
+        fn matched(&mut self, record: &Record) -> std::io::Result<bool> {
+            // only the first match in a line, without asking for them all
+            assert_eq!(1, record.spans.len());
+            self.lines.push((record.line_number, true));
+            Ok(self.lines.iter().filter(|&&(_, matched)| matched).count() < self.stop_after)
+        }
+
+        fn context(&mut self, record: &Record) -> std::io::Result<bool> {
+            self.lines.push((record.line_number, false));
+            Ok(true)
+        }
+
+        fn wants_all_spans(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn searcher_sink() {
+        let contents = "a\nb\nc a a\nd\ne\nf\na\ng";
+        let mut builder = SearcherBuilder::new();
+        builder.pattern("a").before_context(1);
+
+        let mut sink = Collect { lines: Vec::new(), stop_after: 10 };
+        let count = builder.build().unwrap().search_sink(contents.as_bytes(), &mut sink).unwrap();
+        assert_eq!(3, count);
+        assert_eq!(vec![(1, true), (2, false), (3, true), (6, false), (7, true)], sink.lines);
+
+        // the same builder can be changed and built again
+        let searcher = builder.invert(true).before_context(0).max_count(Some(3)).build().unwrap();
+        let lines: Vec<usize> = searcher.search(contents.as_bytes()).map(|record| record.unwrap().line_number).collect();
+        assert_eq!(vec![2, 4, 5], lines);
+
+        // and a sink can stop the search
+        let mut sink = Collect { lines: Vec::new(), stop_after: 2 };
+        let searcher = builder.invert(false).max_count(None).build().unwrap();
+        assert_eq!(2, searcher.search_sink(contents.as_bytes(), &mut sink).unwrap());
+        assert_eq!(vec![(1, true), (3, true)], sink.lines);
+    }
+
+    #[test]
+    fn searcher_slice() {
+        let searcher = SearcherBuilder::new().pattern("duct").build().unwrap();
+        let mut sink = Collect { lines: Vec::new(), stop_after: 10 };
+
+        // CRLF endings and bytes that aren't UTF-8 are handled as when reading, as is a last line
+        // without a newline
+        let bytes = b"Rust:\r\nsafe, fast, pro\xffductive duct.\r\nduct";
+        assert_eq!(2, searcher.search_slice(bytes, &mut sink).unwrap());
+        assert_eq!(vec![(2, true), (3, true)], sink.lines);
+
+        let records: Vec<Record> = searcher.search(&bytes[..]).map(|record| record.unwrap()).collect();
+        assert_eq!("safe, fast, pro\u{fffd}ductive duct.", records[0].line);
+        assert_eq!((7, 38), (records[0].byte_offset, records[1].byte_offset));
+    }
+
+    #[test]
+    fn run_big_files_memory_mapped() {
+        use std::fs;
+
+        // big enough to be memory mapped
+        let dir = tempfile::tempdir().unwrap();
+        let mut contents = "Rust:\nsafe, fast, productive.\nPick three.\n".repeat(2000);
+        contents.push_str("Duct tape");
+        fs::write(dir.path().join("poem.txt"), &contents).unwrap();
+
+        let run = |args: &[&str]| {
+            let output = minigrep().current_dir(dir.path()).args(args).output().unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        let mapped = run(&["-nb", "-C1", "-m3", "duct", "poem.txt"]);
+        assert_eq!(
+            "1-0-Rust:\n2:6:safe, fast, productive.\n3-30-Pick three.\n\
+             4-42-Rust:\n5:48:safe, fast, productive.\n6-72-Pick three.\n\
+             7-84-Rust:\n8:90:safe, fast, productive.\n9-114-Pick three.\n",
+            mapped
+        );
+        assert_eq!(mapped, run(&["-nb", "-C1", "-m3", "--no-mmap", "duct", "poem.txt"]));
+        assert_eq!("6001:Duct tape\n", run(&["-in", "tape", "poem.txt"]));
+        assert_eq!("2001\n", run(&["-ic", "duct", "poem.txt"]));
+    }
+
+    #[test]
+    fn searcher_multiline() {
+        let contents = "INFO start\nERROR boom\nCaused by: disk\nINFO ok\nERROR again\r\nCaused by: net\n";
+        let searcher = SearcherBuilder::new()
+            .pattern(r"ERROR.*\r?\nCaused by")
+            .regex(true)
+            .multiline(true)
+            .after_context(1)
+            .build()
+            .unwrap();
+
+        let records: Vec<Record> = searcher.search(contents.as_bytes()).map(|record| record.unwrap()).collect();
+        let found: Vec<(RecordKind, usize, usize, usize, &str)> = records.iter()
+            .map(|record| (record.kind, record.line_number, record.end_line_number, record.byte_offset, record.line.as_str()))
+            .collect();
+        assert_eq!(vec![
+            (RecordKind::Match, 2, 3, 11, "ERROR boom\nCaused by: disk"),
+            (RecordKind::Context, 4, 4, 38, "INFO ok"),
+            (RecordKind::Match, 5, 6, 46, "ERROR again\r\nCaused by: net"),
+        ], found);
+        assert_eq!(0..20, records[0].spans[0]);
+
+        // ^ and $ match at the start and end of each line, and matches on the same lines are one record
+        let mut builder = SearcherBuilder::new();
+        builder.pattern("^INFO").pattern(r"k$\n^E").regex(true).multiline(true);
+        let lines: Vec<(usize, usize)> = builder.build().unwrap().search(contents.as_bytes())
+            .map(|record| record.unwrap())
+            .map(|record| (record.line_number, record.end_line_number))
+            .collect();
+        assert_eq!(vec![(1, 1), (4, 5)], lines);
+
+        // after the last newline there's no line for an empty match at the very end to select
+        let searcher = SearcherBuilder::new().pattern("^$").regex(true).multiline(true).build().unwrap();
+        assert_eq!(0, searcher.search("a\nb\n".as_bytes()).count());
+        let lines: Vec<usize> = searcher.search("a\n\nb".as_bytes()).map(|record| record.unwrap().line_number).collect();
+        assert_eq!(vec![2], lines);
+    }
+
+    #[test]
+    fn run_multiline() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("trace.txt"), "INFO start\nERROR boom\nCaused by: disk\nINFO ok\n").unwrap();
+        let run = |args: &[&str]| {
+            let output = minigrep().current_dir(dir.path()).args(args).output().unwrap();
+            (output.status.code(), String::from_utf8(output.stdout).unwrap())
+        };
+
+        // without -U a pattern can't match across lines
+        assert_eq!((Some(1), String::new()), run(&["-E", r"boom\nCaused", "trace.txt"]));
+        assert_eq!(
+            (Some(0), "2:ERROR boom\n3:Caused by: disk\n".to_string()),
+            run(&["-U", "-n", "-E", r"boom\nCaused", "trace.txt"])
+        );
+        assert_eq!((Some(0), "1\n".to_string()), run(&["-Uc", "-E", r"boom\nCaused", "trace.txt"]));
+        assert_eq!(
+            (Some(0), "1:INFO start\n4:INFO ok\n".to_string()),
+            run(&["-Uvn", "-E", r"ERROR.*\n.*", "trace.txt"])
+        );
+
+        let (_, json) = run(&["-U", "--json", "-E", r"boom\nCaused", "trace.txt"]);
+        assert_eq!(
+            r#"{"type":"match","file":"trace.txt","line_number":2,"end_line_number":3,"byte_offset":11,"submatches":[{"start":6,"end":17,"text":"boom\nCaused"}],"line":"ERROR boom\nCaused by: disk"}"#,
+            json.lines().nth(1).unwrap()
+        );
+
+        let args = strings(&["-U", "--fuzzy", "1", "needle", "haystack"]);
+        assert_eq!(
+            ConfigError::Conflicts("--multiline".to_string(), "--fuzzy".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn search_compressed_files() {
+        use std::fs;
+        use std::io::Write;
+
+        let poem = "Rust:\nsafe, fast, productive.\nPick three.\nDuct tape\n";
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(poem.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        // two gzip streams one after the other, like logs appended to after rotating
+        fs::write(dir.path().join("poem.txt.gz"), [gzip.clone(), gzip].concat()).unwrap();
+
+        // repeated so it really is compressed, short text is mostly stored as it is
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(poem.repeat(50).as_bytes()).unwrap();
+        fs::write(dir.path().join("poem-gz"), gzip.finish().unwrap()).unwrap();
+
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(poem.as_bytes()).unwrap();
+        fs::write(dir.path().join("poem.txt.bz2"), bzip2.finish().unwrap()).unwrap();
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(poem.as_bytes()).unwrap();
+        fs::write(dir.path().join("poem.txt.xz"), xz.finish().unwrap()).unwrap();
+
+        fs::write(dir.path().join("poem.txt.zst"), zstd::encode_all(poem.as_bytes(), 0).unwrap()).unwrap();
+
+        let run = |args: &[&str]| {
+            let output = minigrep()
+                .current_dir(dir.path())
+                .args(args)
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        assert_eq!(
+            "poem.txt.gz:2\npoem.txt.bz2:1\npoem.txt.xz:1\npoem.txt.zst:1\n",
+            run(&["-c", "duct", "poem.txt.gz", "poem.txt.bz2", "poem.txt.xz", "poem.txt.zst"])
+        );
+        // it's the magic bytes that count, not the name
+        assert_eq!("50\n", run(&["-c", "Pick three", "poem-gz"]));
+        // walked files are decompressed before deciding whether they're binary
+        assert_eq!(
+            "./poem-gz:50\n./poem.txt.bz2:1\n./poem.txt.gz:2\n./poem.txt.xz:1\n./poem.txt.zst:1\n",
+            run(&["-c", "--sort-files", "duct", "."])
+        );
+
+        // stdin is only decompressed with -z
+        let stdin = |args: &[&str]| {
+            let output = minigrep()
+                .args(args)
+                .stdin(fs::File::open(dir.path().join("poem-gz")).unwrap())
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!("0\n", stdin(&["-c", "Pick three"]));
+        assert_eq!("50\n", stdin(&["-zc", "Pick three"]));
+    }
+
+    #[test]
+    fn search_truncated_compressed_file() {
+        use std::fs;
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        // numbered lines compress poorly enough that it fails part way through, not at the start
+        let lines: String = (0..20000).map(|n| format!("{} productive\n", n)).collect();
+        gzip.write_all(lines.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        fs::write(dir.path().join("poem.txt.gz"), &gzip[..gzip.len() / 2]).unwrap();
+        fs::write(dir.path().join("poem.txt"), "Rust:\nsafe, fast, productive.\n").unwrap();
+
+        // failing part way through one file is reported, and the next is still searched
+        for threads in &["1", "2"] {
+            let output = minigrep()
+                .current_dir(dir.path())
+                .args(["-j", threads, "-c", "duct", "poem.txt.gz", "poem.txt"])
+                .output()
+                .unwrap();
+            assert_eq!(Some(2), output.status.code());
+            assert_eq!("poem.txt:1\n", String::from_utf8(output.stdout).unwrap());
+            assert!(String::from_utf8(output.stderr).unwrap().starts_with("minigrep: poem.txt.gz: "));
+        }
+    }
+
+    // strings from strs, to save writing .to_string() on every arg
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn config_defaults_layer_under_args() {
+        // 1. the built in defaults
+        let config = Config::with_defaults(&[], &strings(&["needle"])).unwrap();
+        assert_eq!(ColorChoice::Auto, config.color);
+        assert!(!config.smart_case && !config.hidden);
+        assert_eq!((0, 0), (config.before_context, config.after_context));
+
+        // 3. the config file's flags replace them, values can go on the next line
+        let defaults = strings(&["--color=never", "-S", "--hidden", "--exclude=target", "-C", "2"]);
+        let config = Config::with_defaults(&defaults, &strings(&["needle", "src"])).unwrap();
+        assert_eq!(ColorChoice::Never, config.color);
+        assert!(config.smart_case && config.hidden);
+        assert_eq!(vec!["target"], config.exclude);
+        assert_eq!((2, 2), (config.before_context, config.after_context));
+        assert_eq!(("needle", vec!["src".to_string()]), (config.query.as_str(), config.filenames));
+
+        // 4. and the command line replaces those
+        let args = strings(&["needle", "--color=always", "-s", "--no-hidden", "-A", "1"]);
+        let config = Config::with_defaults(&defaults, &args).unwrap();
+        assert_eq!(ColorChoice::Always, config.color);
+        assert!(!config.smart_case && !config.hidden);
+        assert_eq!((2, 1), (config.before_context, config.after_context));
+
+        // a config file can't give a query or filenames
+        assert_eq!(
+            ConfigError::NotAFlag("needle".to_string()),
+            Config::with_defaults(&strings(&["needle"]), &strings(&["haystack"])).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn config_file_and_env_layers() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("poem.txt"), "Rust:\nsafe, fast, productive.\nPick three.\nDuct tape").unwrap();
+        let config_file = dir.path().join("config");
+        fs::write(&config_file, "# count lines rather than print them\n\n--count\n  --case-sensitive  \n").unwrap();
+
+        let run = |env: &[(&str, &std::path::Path)], args: &[&str]| {
+            let mut command = minigrep();
+            for &(name, value) in env {
+                command.env(name, value);
+            }
+            let output = command.current_dir(dir.path()).args(args).output().unwrap();
+            (output.status.code(), String::from_utf8(output.stdout).unwrap())
+        };
+        let case_insensitive = ("CASE_INSENSITIVE", std::path::Path::new("1"));
+        let config = ("MINIGREP_CONFIG", config_file.as_path());
+
+        // 2. the env var turns on ignoring case
+        assert_eq!("safe, fast, productive.\nDuct tape\n", run(&[case_insensitive], &["duct", "poem.txt"]).1);
+        // 3. the config file overrides the env var, and adds --count
+        assert_eq!("1\n", run(&[case_insensitive, config], &["duct", "poem.txt"]).1);
+        // 4. the command line overrides the config file
+        assert_eq!("2\n", run(&[case_insensitive, config], &["-i", "duct", "poem.txt"]).1);
+        // and --no-config leaves it out altogether
+        assert_eq!("safe, fast, productive.\n", run(&[config], &["--no-config", "duct", "poem.txt"]).1);
+
+        // XDG_CONFIG_HOME is where the config file is looked for without MINIGREP_CONFIG
+        fs::create_dir_all(dir.path().join("xdg/minigrep")).unwrap();
+        fs::copy(&config_file, dir.path().join("xdg/minigrep/config")).unwrap();
+        let mut command = minigrep();
+        let output = command.env_remove("MINIGREP_CONFIG")
+            .env("XDG_CONFIG_HOME", dir.path().join("xdg"))
+            .current_dir(dir.path())
+            .args(["duct", "poem.txt"])
+            .output()
+            .unwrap();
+        assert_eq!("1\n", String::from_utf8(output.stdout).unwrap());
+
+        // a config file that's named but missing is an error
+        let missing = ("MINIGREP_CONFIG", std::path::Path::new("missing"));
+        assert_eq!((Some(2), String::new()), run(&[missing], &["duct", "poem.txt"]));
+
+        // a flag and its value can share a line, split at the first space
+        fs::write(&config_file, "--context 1\n-r  a b\n").unwrap();
+        assert_eq!("Rust:\nsafe, fast, proa bive.\nPick three.\n", run(&[config], &["duct", "poem.txt"]).1);
+        // unless the flag already has its value, and a # after a space starts a comment
+        fs::write(&config_file, "--replace=a b  # spaced out\n--max-count 1\t# just the one\n").unwrap();
+        assert_eq!("safe, fast, proa bive.\n", run(&[config], &["-i", "duct", "poem.txt"]).1);
+        // a value on a line of its own is kept whole
+        fs::write(&config_file, "--replace\nx #1\n").unwrap();
+        assert_eq!("safe, fast, prox #1ive.\n", run(&[config], &["duct", "poem.txt"]).1);
+
+        // -e in the config file would leave the query as a filename, so it's an error
+        fs::write(&config_file, "-e TODO\n").unwrap();
+        assert_eq!((Some(2), String::new()), run(&[config], &["duct", "poem.txt"]));
+        assert_eq!(ConfigError::PatternsInConfigFile,
+                   Config::with_defaults(&strings(&["-e", "TODO"]), &strings(&["duct", "poem.txt"])).unwrap_err());
+    }
+
+    // a writer thread appends to a log, then rotates it by truncating and by renaming, while a
+    // search follows it; the records come back over a channel so a broken follow fails rather than
+    // hangs
+    #[test]
+    fn follow_growing_file() {
+        use std::fs::{self, OpenOptions};
+        use std::io::Write;
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        fs::write(&log, "ERROR old\nINFO x\n").unwrap();
+
+        let follow = Follow::open(&log, Duration::from_millis(5)).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let searcher = SearcherBuilder::new().pattern("ERROR").build().unwrap();
+            for record in searcher.search(follow) {
+                let record = record.unwrap();
+                if sender.send((record.line_number, record.line)).is_err() {
+                    break;
+                }
+            }
+        });
+        let next = || receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        let append = |text: &str| OpenOptions::new().append(true).open(&log).unwrap().write_all(text.as_bytes()).unwrap();
+
+        assert_eq!((1, "ERROR old".to_string()), next());
+
+        // a line is only searched once it's finished
+        append("INFO y\nERROR ha");
+        thread::sleep(Duration::from_millis(50));
+        append("lf\n");
+        assert_eq!((4, "ERROR half".to_string()), next());
+
+        // truncated, then written to again from the start
+        fs::write(&log, "").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        append("ERROR truncated\n");
+        assert_eq!((5, "ERROR truncated".to_string()), next());
+
+        // renamed away, with what's still written to the old file read before the new one
+        fs::rename(&log, dir.path().join("app.log.1")).unwrap();
+        OpenOptions::new().append(true).open(dir.path().join("app.log.1")).unwrap()
+            .write_all(b"ERROR late\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        fs::write(&log, "ERROR new\n").unwrap();
+        assert_eq!((6, "ERROR late".to_string()), next());
+        assert_eq!((7, "ERROR new".to_string()), next());
+    }
+
+    #[test]
+    fn run_follow() {
+        use std::fs::{self, OpenOptions};
+        use std::io::Write;
+        use std::process::Stdio;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        fs::write(&log, "ERROR old\n").unwrap();
+
+        // -m stops following once it's found enough
+        let mut child = minigrep().current_dir(dir.path())
+            .args(["--follow", "-n", "-m", "2", "ERROR", "app.log"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(300));
+        OpenOptions::new().append(true).open(&log).unwrap().write_all(b"INFO\nERROR new\n").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while child.try_wait().unwrap().is_none() {
+            if Instant::now() > deadline {
+                child.kill().unwrap();
+                panic!("--follow didn't stop after -m 2");
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(Some(0), output.status.code());
+        assert_eq!("1:ERROR old\n3:ERROR new\n", String::from_utf8(output.stdout).unwrap());
+
+        let args = strings(&["--follow", "needle"]);
+        assert_eq!(
+            ConfigError::Requires("--follow".to_string(), "a single file".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+        let args = strings(&["--follow", "-c", "needle", "app.log"]);
+        assert_eq!(
+            ConfigError::Conflicts("--follow".to_string(), "--count".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn decode_encodings() {
+        use std::io::{BufRead, BufReader, Read};
+
+        let decode = |bytes: Vec<u8>, encoding: Encoding| {
+            // a tiny buffer splits code units and surrogate pairs between reads
+            let reader: Box<dyn BufRead> = Box::new(BufReader::with_capacity(3, std::io::Cursor::new(bytes)));
+            let mut text = String::new();
+            encoding::decode(reader, encoding).unwrap().read_to_string(&mut text).unwrap();
+            text
+        };
+        let utf16le = |text: &str| text.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect::<Vec<u8>>();
+        let utf16be = |text: &str| text.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect::<Vec<u8>>();
+
+        // a byte order mark is what auto goes by, and is skipped
+        assert_eq!("Straße 😀\n", decode(utf16le("\u{feff}Straße 😀\n"), Encoding::Auto));
+        assert_eq!("Straße 😀\n", decode(utf16be("\u{feff}Straße 😀\n"), Encoding::Auto));
+        assert_eq!("Straße\n", decode("\u{feff}Straße\n".as_bytes().to_vec(), Encoding::Auto));
+        assert_eq!("Straße 😀", decode(utf16be("Straße 😀"), Encoding::Utf16Be));
+        assert_eq!("café", decode(b"caf\xe9".to_vec(), Encoding::Latin1));
+
+        // without a BOM, auto is UTF-8
+        assert_eq!("caf\u{e9}", decode("café".as_bytes().to_vec(), Encoding::Auto));
+        // half a char at the end is replaced
+        let mut bytes = utf16le("\u{feff}ab");
+        bytes.push(b'c');
+        assert_eq!("ab\u{fffd}", decode(bytes, Encoding::Auto));
+
+        assert_eq!(Some(Encoding::Utf16Le), Encoding::from_name("UTF-16LE"));
+        assert_eq!(None, Encoding::from_name("ebcdic"));
+    }
+
+    #[test]
+    fn run_binary_and_encodings() {
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), b"hello\x00world\nneedle here\n").unwrap();
+        let utf16: Vec<u8> = "\u{feff}Straße\nneedle ü\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+        fs::write(dir.path().join("utf16.txt"), utf16).unwrap();
+        fs::write(dir.path().join("latin1.txt"), b"caf\xe9 needle\n").unwrap();
+        let run = |args: &[&str]| {
+            let output = minigrep().current_dir(dir.path()).args(args).output().unwrap();
+            (output.status.code(), String::from_utf8(output.stdout).unwrap())
+        };
+
+        // a binary file named on the command line only says whether it matched, unless -a
+        assert_eq!((Some(0), "Binary file data.bin matches\n".to_string()), run(&["-C1", "needle", "data.bin"]));
+        assert_eq!((Some(1), String::new()), run(&["nothing", "data.bin"]));
+        assert_eq!((Some(0), "2:needle here\n".to_string()), run(&["-n", "--text", "needle", "data.bin"]));
+        assert_eq!((Some(0), "1\n".to_string()), run(&["-c", "needle", "data.bin"]));
+
+        // UTF-16 with a BOM isn't binary, it's decoded; Latin-1 needs --encoding
+        assert_eq!((Some(0), "2:needle ü\n".to_string()), run(&["-n", "needle", "utf16.txt"]));
+        assert_eq!((Some(1), String::new()), run(&["café", "latin1.txt"]));
+        assert_eq!((Some(0), "café needle\n".to_string()), run(&["--encoding", "latin1", "café", "latin1.txt"]));
+
+        // found in a directory, binary files are skipped unless -a
+        let walked = run(&["--sort-files", "needle", "."]).1;
+        assert_eq!("./latin1.txt:caf\u{fffd} needle\n./utf16.txt:needle ü\n", walked);
+        let walked = run(&["--sort-files", "-a", "needle", "."]).1;
+        assert_eq!("./data.bin:needle here\n./latin1.txt:caf\u{fffd} needle\n./utf16.txt:needle ü\n", walked);
+
+        let args = strings(&["--encoding", "ebcdic", "needle"]);
+        assert_eq!(
+            ConfigError::InvalidValue("--encoding".to_string(), "ebcdic".to_string()),
+            Config::new(&args).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn run_with_index() {
+        use std::fs::{self, File};
+
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        fs::create_dir_all(tree.join("src")).unwrap();
+        fs::write(tree.join("a.txt"), "a needle in a haystack\nNEEDLES\n").unwrap();
+        fs::write(tree.join("b.txt"), "nothing here").unwrap();
+        fs::write(tree.join("src/c.rs"), "fn find_needle() {}\nfn other() {}\n").unwrap();
+        fs::write(tree.join("src/d.txt"), "Straße needle\n").unwrap();
+        fs::write(tree.join("data.bin"), b"\x00needle\n").unwrap();
+        let utf16: Vec<u8> = "\u{feff}utf16 needle\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+        fs::write(tree.join("utf16.txt"), utf16).unwrap();
+        let run = |args: &[&str]| {
+            let output = minigrep().current_dir(dir.path()).arg("--sort-files").args(args).output().unwrap();
+            (output.status.code(), String::from_utf8(output.stdout).unwrap())
+        };
+        let same_with_index = |searches: &[&[&str]]| {
+            for args in searches {
+                let mut indexed = vec!["--index"];
+                indexed.extend_from_slice(args);
+                indexed.push("tree");
+                let unindexed = [*args, &["tree"]].concat();
+                assert_eq!(run(&unindexed), run(&indexed), "{:?}", args);
+            }
+        };
+        let searches: &[&[&str]] = &[
+            &["needle"], &["-c", "needle"], &["--json", "needle"], &["-i", "NEEDLE"], &["-i", "STRASSE"],
+            &["-E", r"fn \w+_needle"], &["-E", "(haystack|other)"], &["-l", "zebra"], &["-L", "needle"],
+            &["-v", "needle"], &["-a", "needle"], &["-e", "nothing", "-e", "other"], &["-E", "ne.dle"],
+        ];
+
+        // without an index, --index is an error
+        assert_eq!(Some(2), run(&["--index", "needle", "tree"]).0);
+        assert_eq!(
+            (Some(0), "indexed 6 files in tree (6 read, 0 unchanged, 0 removed)\n".to_string()),
+            run(&["index", "build", "tree"])
+        );
+        same_with_index(searches);
+
+        // a file changed since the index was built is searched as usual
+        fs::write(tree.join("src/c.rs"), "fn zebra() {}\n").unwrap();
+        fs::remove_file(tree.join("b.txt")).unwrap();
+        same_with_index(searches);
+        same_with_index(&[&["zebra"]]);
+        assert_eq!(
+            (Some(0), "indexed 5 files in tree (1 read, 4 unchanged, 1 removed)\n".to_string()),
+            run(&["index", "build", "tree"])
+        );
+        same_with_index(searches);
+
+        // files the index rules out aren't read at all: one that's changed without its size or
+        // modification time changing is only found without --index
+        let file = File::options().write(true).open(tree.join("a.txt")).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        fs::write(tree.join("a.txt"), "a zebra! in a haystack\nNEEDLES\n").unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!("tree/a.txt:a zebra! in a haystack\ntree/src/c.rs:fn zebra() {}\n", run(&["zebra", "tree"]).1);
+        assert_eq!("tree/src/c.rs:fn zebra() {}\n", run(&["--index", "zebra", "tree"]).1);
+
+        // a corrupt index is an error, even one claiming a name four billion bytes long
+        let mut corrupt = b"minigrep index 1\n".to_vec();
+        corrupt.extend_from_slice(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        fs::write(tree.join(".minigrep-index"), corrupt).unwrap();
+        let output = minigrep().current_dir(dir.path()).args(["--index", "zebra", "tree"]).output().unwrap();
+        assert_eq!(Some(2), output.status.code());
+        assert!(String::from_utf8(output.stderr).unwrap().contains("isn't a minigrep index"));
+    }
+
+    #[test]
+    fn index_build_unreadable_file() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "needle\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "needle\n").unwrap();
+        fs::set_permissions(dir.path().join("b.txt"), fs::Permissions::from_mode(0o000)).unwrap();
+
+        // root can read it anyway, so there's nothing to test
+        let readable = fs::read(dir.path().join("b.txt")).is_ok();
+        let build = minigrep().current_dir(dir.path()).args(["index", "build", "."]).output().unwrap();
+        fs::set_permissions(dir.path().join("b.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+        if readable {
+            return;
+        }
+
+        // it's reported and left out of the index, which still gets written
+        assert_eq!(Some(2), build.status.code());
+        assert_eq!("indexed 1 files in . (1 read, 0 unchanged, 0 removed)\n", String::from_utf8(build.stdout).unwrap());
+        assert_eq!("minigrep: ./b.txt: Permission denied (os error 13)\n", String::from_utf8(build.stderr).unwrap());
+        // so once it can be read, it's searched
+        let output = minigrep().current_dir(dir.path()).args(["--sort-files", "--index", "needle", "."]).output().unwrap();
+        assert_eq!("./a.txt:needle\n./b.txt:needle\n", String::from_utf8(output.stdout).unwrap());
+    }
+
+    // the text of rendered lines, without their colors
+    fn plain(lines: &[String]) -> Vec<String> {
+        lines.iter()
+            .map(|line| {
+                let mut text = String::new();
+                let mut chars = line.chars();
+                while let Some(c) = chars.next() {
+                    if c == '\x1b' {
+                        chars.by_ref().find(|c| c.is_ascii_alphabetic());
+                    } else {
+                        text.push(c);
+                    }
+                }
+                text.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interactive_browser() {
+        use minigrep::interactive::{Action, Browser, Key};
+        use std::fs;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().display().to_string();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("notes.txt"), "one\ntwo needle\nthree\n").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {\n    let needle = 1;\n    needless(needle);\n}\n").unwrap();
+
+        let config = Config::new(&strings(&["--interactive", "--sort-files", "nee", &root])).unwrap();
+        let mut browser = Browser::new(config).unwrap();
+        assert_eq!(3, browser.hits());
+
+        // typing narrows the search down
+        for c in "dle".chars() {
+            assert_eq!(Action::Redraw, browser.key(Key::Char(c)));
+        }
+        assert_eq!("needle", browser.query());
+        let notes = format!("{}/notes.txt", root);
+        let main = format!("{}/src/main.rs", root);
+        let screen = plain(&browser.render(60, 14));
+        assert_eq!(14, screen.len());
+        assert_eq!("> needle", &screen[0][..8]);
+        assert!(screen[0].ends_with("3 hits in 2 files"));
+        assert_eq!(vec![
+            notes.clone(),
+            "     2: two needle".to_string(),
+            main.clone(),
+            "     2:     let needle = 1;".to_string(),
+            "     3:     needless(needle);".to_string(),
+        ], screen[1..6].to_vec());
+        // with a preview of the lines around the selected hit
+        assert!(screen[9].starts_with(&format!("── {}:2 ──", notes)));
+        assert_eq!(vec!["     1  one", "     2  two needle", "     3  three", ""], screen[10..].to_vec());
+
+        // moving the selection moves the preview, and enter opens the selected hit
+        browser.key(Key::Down);
+        browser.key(Key::Down);
+        let screen = plain(&browser.render(60, 14));
+        assert!(screen[9].starts_with(&format!("── {}:3 ──", main)));
+        assert_eq!("     4  }", screen[13]);
+        assert_eq!(Action::Open(dir.path().join("src/main.rs"), 3), browser.key(Key::Enter));
+        browser.key(Key::Down);
+        assert_eq!(Action::Open(dir.path().join("src/main.rs"), 3), browser.key(Key::Enter));
+
+        // one more char, with some of the files that had hits ruled out
+        browser.key(Key::Char('s'));
+        assert_eq!(1, browser.hits());
+        browser.key(Key::Backspace);
+        assert_eq!(3, browser.hits());
+        browser.key(Key::Ctrl('u'));
+        assert_eq!(0, browser.hits());
+        assert_eq!(Action::Quit, browser.key(Key::Esc));
+
+        // ignoring case, more of the query can match more: s doesn't match Maß, but ss does
+        fs::write(dir.path().join("street.txt"), "Maß\n").unwrap();
+        for flags in &["-i", "-S"] {
+            let config = Config::new(&strings(&["--interactive", "--sort-files", flags, "s", &root])).unwrap();
+            let mut browser = Browser::new(config).unwrap();
+            assert_eq!(1, browser.hits());
+            browser.key(Key::Char('s'));
+            assert_eq!(2, browser.hits());
+            assert!(plain(&browser.render(60, 8))[3].ends_with("street.txt"));
+        }
+        fs::remove_file(dir.path().join("street.txt")).unwrap();
+
+        // a regex that isn't finished yet says what's wrong rather than searching
+        let config = Config::new(&strings(&["--interactive", "-E", "need(le|", &root])).unwrap();
+        let mut browser = Browser::new(config).unwrap();
+        assert!(plain(&browser.render(60, 5))[0].contains("regex parse error"));
+        browser.key(Key::Char(')'));
+        assert_eq!(3, browser.hits());
+    }
+
+    #[test]
+    fn interactive_keys_and_editor() {
+        use minigrep::interactive::{editor_command, parse_keys, Key};
+        use std::path::Path;
+
+        assert_eq!(
+            vec![Key::Char('a'), Key::Char('ß'), Key::Up, Key::Down, Key::PageDown, Key::Enter, Key::Backspace,
+                 Key::Ctrl('u'), Key::Esc],
+            parse_keys("aß\x1b[A\x1bOB\x1b[6~\r\x7f\x15\x1b".as_bytes())
+        );
+        // sequences that aren't known are left out
+        assert_eq!(vec![Key::Char('x')], parse_keys(b"\x1b[1;5Cx"));
+
+        let path = Path::new("src/main.rs");
+        assert_eq!(strings(&["vim", "+12", "src/main.rs"]), editor_command("vim", path, 12));
+        assert_eq!(strings(&["/usr/bin/emacs", "-nw", "+12", "src/main.rs"]), editor_command("/usr/bin/emacs -nw", path, 12));
+        assert_eq!(strings(&["code", "--wait", "--goto", "src/main.rs:12"]), editor_command("code --wait", path, 12));
+        assert_eq!(strings(&["hx", "src/main.rs:12"]), editor_command("hx", path, 12));
+
+        // the query is optional, and it searches the current directory; stdin can't be searched again
+        let config = Config::new(&strings(&["--interactive"])).unwrap();
+        assert_eq!(("", vec![".".to_string()]), (config.query.as_str(), config.filenames));
+        assert_eq!(
+            ConfigError::Conflicts("--interactive".to_string(), "standard input".to_string()),
+            Config::new(&strings(&["--interactive", "needle", "-"])).unwrap_err()
+        );
+    }
 }